@@ -1,4 +1,33 @@
 pub const TITA_FLOW_SEED: &[u8] = b"tita-flow";
 pub const TITA_PROPOSAL_SEED: &[u8] = b"tita-proposal";
 pub const TITA_CONTRIBUTION_SEED: &[u8] = b"tita-contribution";
-pub const TITA_FLOW_TA_SEED: &[u8] = b"tita-flow-ta";
\ No newline at end of file
+pub const TITA_FLOW_TA_SEED: &[u8] = b"tita-flow-ta";
+pub const TITA_VOTE_SEED: &[u8] = b"tita-vote";
+pub const TITA_VAULT_SEED: &[u8] = b"tita-vault";
+pub const TITA_VAULT_TA_SEED: &[u8] = b"tita-vault-ta";
+
+// Amounts are scaled by this before taking the quadratic voting root
+pub const QUADRATIC_VOTING_SCALE: u64 = 1_000_000;
+
+// After a flow's end date plus this period anyone can close it on the creator's behalf
+pub const CLOSE_FLOW_GRACE_PERIOD: i64 = 30 * 24 * 60 * 60;
+
+// Most milestones a flow can have, matches the max_len on Flow::milestones
+pub const MAX_MILESTONES: usize = 10;
+
+// Governance used for program-opened proposals when the creator doesn't set any
+pub const DEFAULT_VOTING_PERIOD: i64 = 3 * 24 * 60 * 60;
+pub const DEFAULT_QUORUM_PERCENTAGE: u16 = 2_000;
+pub const DEFAULT_APPROVAL_PERCENTAGE: u16 = 5_000;
+
+// Payout splits are expressed in basis points of this total
+pub const BASIS_POINTS_DENOMINATOR: u16 = 10_000;
+
+// Most recipients a flow's payouts can be split between
+pub const MAX_PAYOUT_SPLITS: usize = 8;
+
+// Longest evidence URI a milestone submission can store
+pub const MAX_EVIDENCE_URI_LENGTH: usize = 128;
+
+// Precision of the per token expired milestone refund accumulator
+pub const REFUND_PER_TOKEN_SCALE: u128 = 1_000_000_000_000;
@@ -145,4 +145,154 @@ pub enum TitaErrors {
 
     #[msg("Insufficient treasury funds")]
     InsufficientTreasuryFunds,
+
+    #[msg("Grant flows can only be funded by their donor")]
+    GrantFlowContribution,
+
+    #[msg("Only the donor can fund or govern this grant")]
+    UnauthorizedDonor,
+
+    #[msg("Funds can only be paid out to the flow's beneficiary")]
+    InvalidBeneficiary,
+
+    #[msg("Invalid grant donor or beneficiary")]
+    InvalidGrantParticipants,
+
+    #[msg("This instruction is only available for grant flows")]
+    NotGrantFlow,
+
+    #[msg("The voting period has not ended yet")]
+    VotingStillActive,
+
+    #[msg("The proposal can't be canceled after votes have been cast")]
+    VotesAlreadyCast,
+
+    #[msg("The provided proposer doesn't match the proposal")]
+    InvalidProposer,
+
+    #[msg("All-or-nothing flows need an end date")]
+    AllOrNothingRequiresEndDate,
+
+    #[msg("This flow doesn't use all-or-nothing funding")]
+    NotAllOrNothingFlow,
+
+    #[msg("The flow has not ended yet")]
+    FlowNotEnded,
+
+    #[msg("All-or-nothing flows must be settled first")]
+    FlowNotSettled,
+
+    #[msg("Milestone funds are still locked in the flow")]
+    LockedFundsRemaining,
+
+    #[msg("Only the creator can close the flow before the grace period ends")]
+    UnauthorizedClose,
+
+    #[msg("The provided creator doesn't match the flow")]
+    InvalidCreator,
+
+    #[msg("Flow balances don't add up to the amount raised")]
+    BalanceMismatch,
+
+    #[msg("Funds have already been withdrawn from this flow")]
+    FundsAlreadyWithdrawn,
+
+    #[msg("Payout splits need between 2 and 8 distinct recipients")]
+    InvalidSplitRecipients,
+
+    #[msg("Payout split shares must be non-zero and sum to 10000 basis points")]
+    InvalidSplitShares,
+
+    #[msg("This flow has no payout splits")]
+    NoPayoutSplits,
+
+    #[msg("Flows with payout splits are paid out through distribute")]
+    PayoutSplitsConfigured,
+
+    #[msg("A token account is required for every split recipient, in split order")]
+    InvalidSplitAccounts,
+
+    #[msg("Streaming releases need a flow with milestones")]
+    StreamingRequiresMilestones,
+
+    #[msg("Voting period must be positive and thresholds at most 10000 basis points")]
+    InvalidGovernanceConfig,
+
+    #[msg("Evidence URI must be between 1 and 128 characters")]
+    InvalidEvidenceUri,
+
+    #[msg("Challenge period must be positive")]
+    InvalidChallengePeriod,
+
+    #[msg("This flow doesn't use optimistic milestone approval")]
+    NotOptimisticFlow,
+
+    #[msg("Milestone hasn't been submitted for approval")]
+    MilestoneNotSubmitted,
+
+    #[msg("The milestone's challenge window is still open")]
+    ChallengeWindowActive,
+
+    #[msg("The milestone's challenge window has closed")]
+    ChallengeWindowClosed,
+
+    #[msg("A proposal account is required to open the completion vote")]
+    ProposalAccountRequired,
+
+    #[msg("Milestone has expired")]
+    MilestoneExpired,
+
+    #[msg("The milestone's deadline hasn't passed yet")]
+    MilestoneDeadlineNotPassed,
+
+    #[msg("A proposal about this milestone is still open")]
+    MilestoneProposalActive,
+
+    #[msg("Expired milestone refunds are still waiting to be claimed")]
+    UnclaimedRefunds,
+
+    #[msg("Milestone ids must be unique")]
+    DuplicateMilestoneId,
+
+    #[msg("Milestone deadlines must be in increasing order")]
+    MilestoneDeadlinesNotIncreasing,
+
+    #[msg("Earlier milestones have to be completed first")]
+    MilestoneOutOfOrder,
+
+    #[msg("Release must be between 1 and 10000 basis points")]
+    InvalidReleasePercentage,
+
+    #[msg("Part of this milestone has already been released")]
+    MilestonePartiallyReleased,
+
+    #[msg("Milestone vaults need milestones and flexible funding")]
+    InvalidMilestoneVaults,
+
+    #[msg("Flow doesn't use milestone vaults")]
+    NotVaultFlow,
+
+    #[msg("Vault accounts don't match the flow's outstanding milestones")]
+    InvalidVaultAccounts,
+
+    #[msg("Hard cap must be at least the goal and wallet limits within it")]
+    InvalidContributionCaps,
+
+    #[msg("Flow has reached its hard cap")]
+    HardCapReached,
+
+    #[msg("Contribution is below the flow's minimum per wallet")]
+    ContributionBelowMinimum,
+
+    #[msg("Contribution is above the flow's maximum per wallet")]
+    ContributionAboveMaximum,
+
+    #[msg("Proposal voting rules can't be looser than the flow's governance")]
+    GovernanceBelowFlowSettings,
+
+    #[msg("Only the donor and the creator can propose on a grant")]
+    UnauthorizedProposer,
+
+    #[msg("Milestone vaults hold tokens they have released, pass or sync them first")]
+    VaultsNotSynced,
 }
@@ -0,0 +1,109 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{close_account, CloseAccount, Mint, TokenAccount, TokenInterface};
+
+use crate::{
+    errors::TitaErrors,
+    instructions::release_vaults,
+    states::{Flow, FlowStatus},
+};
+
+/// Lets the creator abort a flow before any funds have been withdrawn
+///
+/// A flow that never received contributions is closed right away and its rent
+/// returned to the creator. Otherwise it moves to Canceled so contributors can
+/// claim refunds. The reason code is only recorded in the emitted event.
+/// Flows with milestone vaults take every vault holding tokens and its token
+/// account as remaining accounts, they're emptied into flow_ta for the refunds.
+#[derive(Accounts)]
+pub struct CancelFlow<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = flow.creator == creator.key() @ TitaErrors::UnauthorizedCancellation,
+        constraint = flow.is_open() @ TitaErrors::CannotCancelFlow,
+        constraint = flow.withdrawn == 0 @ TitaErrors::FundsAlreadyWithdrawn,
+    )]
+    pub flow: Account<'info, Flow>,
+
+    #[account(
+        mut,
+        constraint = flow_token_account.key() == flow.flow_ta @ TitaErrors::InvalidFlowTokenAccount,
+    )]
+    pub flow_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = token_mint.key() == flow.token_mint @ TitaErrors::InvalidTokenMint,
+    )]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> CancelFlow<'info> {
+    pub fn cancel_flow(&mut self, reason: u8, vault_accounts: &'info [AccountInfo<'info>]) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+
+        // Nothing to refund, so the accounts can be closed straight away
+        let close_now = self.flow.raised == 0 && self.flow_token_account.amount == 0;
+
+        emit!(FlowCanceledEvent {
+            flow: self.flow.key(),
+            creator: self.flow.creator,
+            reason,
+            raised: self.flow.raised,
+            closed: close_now,
+            timestamp: current_time,
+        });
+
+        if close_now {
+            let flow_seeds = self.flow.signer_seeds();
+            let seeds: &[&[u8]] = &flow_seeds;
+
+            close_account(CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                CloseAccount {
+                    account: self.flow_token_account.to_account_info(),
+                    destination: self.creator.to_account_info(),
+                    authority: self.flow.to_account_info(),
+                },
+                &[seeds],
+            ))?;
+
+            return self.flow.close(self.creator.to_account_info());
+        }
+
+        let flow = &mut self.flow;
+
+        // Streams stop here, whatever hasn't vested stays refundable
+        flow.vest_streams(current_time)?;
+        flow.flow_status = FlowStatus::Canceled;
+
+        // Any open proposal can no longer be voted on or executed
+        flow.active_proposal = None;
+
+        release_vaults(
+            flow,
+            vault_accounts,
+            Some(&self.flow_token_account),
+            Some(&self.token_mint),
+            Some(&self.token_program),
+        )?;
+        flow.check_balances()?;
+
+        msg!("Flow {} has been canceled by its creator", flow.flow_id);
+
+        Ok(())
+    }
+}
+
+// Event emitted when the creator cancels a flow
+#[event]
+pub struct FlowCanceledEvent {
+    pub flow: Pubkey,
+    pub creator: Pubkey,
+    pub reason: u8,
+    pub raised: u64,
+    pub closed: bool,
+    pub timestamp: i64,
+}
@@ -0,0 +1,69 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    errors::TitaErrors,
+    states::{Flow, Proposal, ProposalStatus},
+};
+
+/// Withdraws a proposal before anyone has voted on it
+///
+/// Can be called by the proposer or the flow creator. The proposal account is
+/// closed and its rent goes back to the proposer.
+#[derive(Accounts)]
+pub struct CancelProposal<'info> {
+    #[account(mut)]
+    pub canceler: Signer<'info>,
+
+    #[account(
+        mut,
+        close = proposer,
+        constraint = proposal.status == ProposalStatus::Active @ TitaErrors::ProposalNotActive,
+    )]
+    pub proposal: Account<'info, Proposal>,
+
+    #[account(
+        mut,
+        address = proposal.proposer @ TitaErrors::InvalidProposer,
+    )]
+    pub proposer: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = flow.key() == proposal.flow @ TitaErrors::InvalidFlow,
+    )]
+    pub flow: Account<'info, Flow>,
+}
+
+impl<'info> CancelProposal<'info> {
+    pub fn cancel_proposal(&mut self) -> Result<()> {
+        let proposal = &mut self.proposal;
+        let flow = &mut self.flow;
+
+        proposal.cancel(&self.canceler.key(), &flow.creator)?;
+
+        // Free the flow for a new proposal
+        let current_time = Clock::get()?.unix_timestamp;
+
+        if flow.active_proposal == Some(proposal.key()) {
+            flow.close_active_proposal(proposal.created_at, current_time);
+        }
+
+        emit!(ProposalCanceledEvent {
+            flow: flow.key(),
+            proposal: proposal.key(),
+            canceled_by: self.canceler.key(),
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+}
+
+// Event emitted when a proposal is withdrawn
+#[event]
+pub struct ProposalCanceledEvent {
+    pub flow: Pubkey,
+    pub proposal: Pubkey,
+    pub canceled_by: Pubkey,
+    pub timestamp: i64,
+}
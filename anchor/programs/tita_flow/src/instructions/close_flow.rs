@@ -0,0 +1,189 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{
+    close_account, transfer_checked, CloseAccount, Mint, TokenAccount, TokenInterface,
+    TransferChecked,
+};
+
+use crate::{
+    constants::CLOSE_FLOW_GRACE_PERIOD,
+    errors::TitaErrors,
+    states::{Flow, FlowStatus, FundingMode},
+};
+
+/// Closes a finished flow
+///
+/// A flow can be closed once its end date has passed or all of its milestones
+/// are completed. Any remaining available funds are sent to the beneficiary,
+/// the flow is marked Completed and the rent of the flow token account is
+/// returned to the creator. The flow account itself stays, its key seeds every
+/// contribution, proposal and vault of the flow, so the flow id can't be reused
+/// while those are still around. Before the grace period after the end date only
+/// the creator can close the flow.
+#[derive(Accounts)]
+pub struct CloseFlow<'info> {
+    pub closer: Signer<'info>,
+
+    #[account(
+        mut,
+        address = flow.creator @ TitaErrors::InvalidCreator,
+    )]
+    pub creator: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = flow.flow_status != FlowStatus::Canceled @ TitaErrors::FlowCanceled,
+        constraint = flow.active_proposal.is_none() @ TitaErrors::ActiveProposalExists,
+    )]
+    pub flow: Account<'info, Flow>,
+
+    #[account(
+        mut,
+        constraint = flow_token_account.key() == flow.flow_ta @ TitaErrors::InvalidFlowTokenAccount,
+    )]
+    pub flow_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = recipient_token_account.mint == flow.token_mint @ TitaErrors::InvalidTokenMint,
+        constraint = recipient_token_account.owner == flow.beneficiary @ TitaErrors::InvalidBeneficiary,
+    )]
+    pub recipient_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = token_mint.key() == flow.token_mint @ TitaErrors::InvalidTokenMint,
+    )]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> CloseFlow<'info> {
+    pub fn close_flow(&mut self) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+
+        // The flow must be over, either by date or by delivering every milestone
+        let ended_by_date = self.flow.end_date.is_some_and(|end_date| current_time > end_date);
+        require!(
+            ended_by_date || self.flow.all_milestones_resolved(),
+            TitaErrors::FlowNotEnded
+        );
+
+        if self.flow.funding_mode == FundingMode::AllOrNothing {
+            require!(
+                self.flow.flow_status == FlowStatus::Completed,
+                TitaErrors::FlowNotSettled
+            );
+        }
+
+        // Streams that have run their course count as available
+        self.flow.vest_streams(current_time)?;
+
+        // Funds still reserved for milestones can't be swept
+        require!(self.flow.balance == 0, TitaErrors::LockedFundsRemaining);
+
+        // Released and refunded funds have to be synced out of the milestone
+        // vaults first, nothing can reach them once the flow is closed
+        require!(self.flow.vault_holdings == 0, TitaErrors::VaultsNotSynced);
+
+        // Split payouts have to go through distribute rather than the sweep
+        require!(
+            self.flow.splits.is_none() || self.flow.available == 0,
+            TitaErrors::PayoutSplitsConfigured
+        );
+
+        // Anyone can tidy up after the grace period, before that it's the creator's call
+        let grace_period_over = self.flow.end_date.is_some_and(|end_date| {
+            current_time > end_date.saturating_add(CLOSE_FLOW_GRACE_PERIOD)
+        });
+        require!(
+            self.closer.key() == self.flow.creator || grace_period_over,
+            TitaErrors::UnauthorizedClose
+        );
+
+        // Contributors get the grace period to claim expired milestone refunds,
+        // counted from the flow's last date, after that the rest is swept too.
+        // Rounding dust left after everyone claimed doesn't hold the flow open.
+        let refunds_lapsed = self.flow.last_deadline().is_some_and(|last_deadline| {
+            current_time > last_deadline.saturating_add(CLOSE_FLOW_GRACE_PERIOD)
+        });
+        require!(
+            self.flow.refund_pool_is_dust() || refunds_lapsed,
+            TitaErrors::UnclaimedRefunds
+        );
+
+        let flow_seeds = self.flow.signer_seeds();
+        let seeds: &[&[u8]] = &flow_seeds;
+
+        let signer_seeds = &[seeds];
+
+        // Sweep whatever is left in the treasury, which is the unwithdrawn available
+        // amount and any expired milestone refunds nobody claimed
+        let swept = self.flow_token_account.amount;
+        if swept > 0 {
+            transfer_checked(
+                CpiContext::new_with_signer(
+                    self.token_program.to_account_info(),
+                    TransferChecked {
+                        from: self.flow_token_account.to_account_info(),
+                        mint: self.token_mint.to_account_info(),
+                        to: self.recipient_token_account.to_account_info(),
+                        authority: self.flow.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                swept,
+                self.token_mint.decimals,
+            )?;
+        }
+
+        // Return the token account rent to the creator
+        close_account(CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            CloseAccount {
+                account: self.flow_token_account.to_account_info(),
+                destination: self.creator.to_account_info(),
+                authority: self.flow.to_account_info(),
+            },
+            signer_seeds,
+        ))?;
+
+        let flow = &mut self.flow;
+
+        flow.withdrawn = flow.withdrawn
+            .checked_add(flow.available)
+            .and_then(|withdrawn| withdrawn.checked_add(flow.refund_pool))
+            .ok_or(TitaErrors::MathOverflow)?;
+        flow.available = 0;
+        flow.refund_pool = 0;
+        flow.flow_status = FlowStatus::Completed;
+
+        flow.check_balances()?;
+
+        emit!(FlowClosedEvent {
+            flow: flow.key(),
+            flow_id: flow.flow_id.clone(),
+            creator: flow.creator,
+            status: flow.flow_status,
+            raised: flow.raised,
+            withdrawn: flow.withdrawn,
+            refunded: flow.total_refunded,
+            swept,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+}
+
+// Final summary of a flow, emitted when its token account is closed
+#[event]
+pub struct FlowClosedEvent {
+    pub flow: Pubkey,
+    pub flow_id: String,
+    pub creator: Pubkey,
+    pub status: FlowStatus,
+    pub raised: u64,
+    pub withdrawn: u64,
+    pub refunded: u64,
+    pub swept: u64,
+    pub timestamp: i64,
+}
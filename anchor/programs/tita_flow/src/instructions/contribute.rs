@@ -4,9 +4,15 @@ use anchor_spl::token_interface::{transfer_checked, Mint, TokenAccount, TokenInt
 use crate::{
     constants::TITA_CONTRIBUTION_SEED,
     errors::TitaErrors,
-    states::{Contribution, Flow, FlowStatus},
+    states::{Contribution, Flow, FlowKind, Vault},
 };
 
+/// Contributes to a RAISE flow
+///
+/// Flows with milestone vaults take each outstanding milestone's vault and
+/// vault token account as remaining accounts, in milestone order. The locked
+/// part of the contribution goes straight into them, the rest to flow_ta.
+
 #[derive(Accounts)]
 #[instruction(amount: u64)]
 pub struct Contribute<'info> {
@@ -14,7 +20,8 @@ pub struct Contribute<'info> {
     pub contributor: Signer<'info>,
 
     #[account(mut,
-        constraint = flow.flow_status == FlowStatus::Active @ TitaErrors::FlowNotActive,
+        constraint = flow.is_open() @ TitaErrors::FlowNotActive,
+        constraint = flow.kind == FlowKind::Raise @ TitaErrors::GrantFlowContribution,
         constraint = flow.token_mint == token_mint.key() @ TitaErrors::InvalidTokenMint,
     )]
     pub flow: Account<'info, Flow>,
@@ -41,7 +48,7 @@ pub struct Contribute<'info> {
 
     #[account(
         mut,
-        constraint = flow_token_account.mint == token_mint.key() @ TitaErrors::InvalidTokenAccount,
+        constraint = flow_token_account.key() == flow.flow_ta @ TitaErrors::InvalidFlowTokenAccount,
     )]
     pub flow_token_account: InterfaceAccount<'info, TokenAccount>,
 
@@ -51,114 +58,45 @@ pub struct Contribute<'info> {
 }
 
 impl<'info> Contribute<'info> {
-    pub fn contribute(&mut self, amount: u64, contribution_bump: u8) -> Result<()> {
+    pub fn contribute(
+        &mut self,
+        amount: u64,
+        contribution_bump: u8,
+        vault_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
         // Validate the contribution amount
         require!(amount > 0, TitaErrors::InvalidContributionAmount);
 
+        let current_time = Clock::get()?.unix_timestamp;
+
         // Check if flow is still accepting contributions
         if let Some(end_date) = self.flow.end_date {
-            let current_time = Clock::get()?.unix_timestamp;
             require!(current_time <= end_date, TitaErrors::FlowEnded);
         }
 
-        // Check if flow is accepting contributions (start time)
-        // if let Some(start_date) = self.flow.start_date {
-        //     let current_time = Clock::get()?.unix_timestamp;
-        //     require!(current_time >= start_date, TitaErrors::FlowNotStarted);
-        // }
+        // Check if flow is accepting contributions (start time), opening it if it was pending
+        self.flow.activate_if_started(current_time)?;
 
-        // Transfer tokens to token account
-        transfer_checked(
-            CpiContext::new(
-                self.token_program.to_account_info(),
-                anchor_spl::token_interface::TransferChecked {
-                    from: self.contributor_token_account.to_account_info(),
-                    mint: self.token_mint.to_account_info(),
-                    to: self.flow_token_account.to_account_info(),
-                    authority: self.contributor.to_account_info(),
-                },
-            ),
+        // Only take what fits under the hard cap, the excess never leaves the contributor's account
+        let requested = amount;
+        let amount = self
+            .flow
+            .accepted_contribution(requested, self.contribution.total_amount)?;
+
+        deposit_contribution(
+            &mut self.flow,
+            &mut self.contribution,
             amount,
-            self.token_mint.decimals,
+            contribution_bump,
+            current_time,
+            vault_accounts,
+            self.contributor.to_account_info(),
+            &self.contributor_token_account,
+            &self.flow_token_account,
+            &self.token_mint,
+            self.token_program.to_account_info(),
         )?;
 
-        // Initialize contribution if new
-        let current_timestamp = Clock::get()?.unix_timestamp;
-
-        if self.contribution.contribution_count == 0 {
-            // First-time setup
-            self.contribution.flow = self.flow.key();
-            self.contribution.contributor = self.contributor.key();
-            self.contribution.token_mint = self.token_mint.key();
-            self.contribution.total_amount = 0; // Will add amount below
-            self.contribution.first_contribution = current_timestamp;
-            self.contribution.last_contribution = current_timestamp;
-            self.contribution.contribution_count = 0; // Will increment below
-
-            self.contribution.refunded = false;
-            self.contribution.refund_amount = 0;
-            self.contribution.refunded_at = None;
-
-            self.contribution.bump = contribution_bump;
-        }
-
-        // Update the contribution account
-        self.contribution.total_amount = self
-            .contribution
-            .total_amount
-            .checked_add(amount)
-            .ok_or(TitaErrors::MathOverflow)?;
-
-        // if there's no milestone all should be available for withdrawal
-        if self.flow.milestones.is_none() {
-            self.flow.available = self
-                .flow
-                .available
-                .checked_add(amount)
-                .ok_or(TitaErrors::MathOverflow)?;
-        } else if let Some(milestones) = &self.flow.milestones {
-            // If there are milestones, calculate their total
-            let milestone_total = milestones.iter().fold(0u64, |sum, milestone| {
-                sum.checked_add(milestone.amount).unwrap_or(sum)
-            });
-
-            // If milestone total is less than goal, a portion of each contribution should be immediately available
-            if milestone_total < self.flow.goal {
-                // Calculate the proportion that should be immediately available
-                let available_ratio = self.flow.goal.checked_sub(milestone_total).unwrap_or(0);
-
-                // Calculate the immediate available amount from this contribution
-                // Using saturating multiplication to avoid overflow
-                let immediate_available = amount
-                    .checked_mul(available_ratio)
-                    .and_then(|product| product.checked_div(self.flow.goal))
-                    .unwrap_or(0);
-
-                // Add the immediate available amount to the flow's available balance
-                if immediate_available > 0 {
-                    self.flow.available = self
-                        .flow
-                        .available
-                        .checked_add(immediate_available)
-                        .ok_or(TitaErrors::MathOverflow)?;
-                }
-            }
-        }
-
-        self.contribution.last_contribution = current_timestamp;
-        self.contribution.contribution_count = self
-            .contribution
-            .contribution_count
-            .checked_add(1)
-            .ok_or(TitaErrors::MathOverflow)?;
-
-        // Update the flow account
-        self.flow.raised = self
-            .flow
-            .raised
-            .checked_add(amount)
-            .ok_or(TitaErrors::MathOverflow)?;
-
         // If this is a new contributor (first contribution), increment the flow's contributor count
         if self.contribution.contribution_count == 1 {
             self.flow.contributor_count = self
@@ -168,25 +106,199 @@ impl<'info> Contribute<'info> {
                 .ok_or(TitaErrors::MathOverflow)?;
         }
 
+        self.flow.check_balances()?;
+
         // Emit contribution event
         emit!(ContributionEvent {
             flow: self.flow.key(),
             contributor: self.contributor.key(),
             amount,
+            excess_returned: requested - amount,
             total_contributed: self.contribution.total_amount,
-            timestamp: current_timestamp,
+            timestamp: current_time,
         });
 
         Ok(())
     }
 }
 
+// Records a contribution on both accounts and moves the tokens in, the locked
+// part into the milestone vaults and the rest into flow_ta. Shared by contribute
+// and fund_grant, which only differ in who may deposit and what they emit.
+#[allow(clippy::too_many_arguments)]
+pub fn deposit_contribution<'info>(
+    flow: &mut Account<'info, Flow>,
+    contribution: &mut Account<'info, Contribution>,
+    amount: u64,
+    contribution_bump: u8,
+    current_time: i64,
+    vault_accounts: &'info [AccountInfo<'info>],
+    contributor: AccountInfo<'info>,
+    contributor_token_account: &InterfaceAccount<'info, TokenAccount>,
+    flow_token_account: &InterfaceAccount<'info, TokenAccount>,
+    token_mint: &InterfaceAccount<'info, Mint>,
+    token_program: AccountInfo<'info>,
+) -> Result<()> {
+    // Initialize contribution if new
+    if contribution.contribution_count == 0 {
+        // First-time setup
+        contribution.flow = flow.key();
+        contribution.contributor = contributor.key();
+        contribution.token_mint = token_mint.key();
+        contribution.total_amount = 0; // Will add amount below
+        contribution.first_contribution = current_time;
+        contribution.last_contribution = current_time;
+        contribution.contribution_count = 0; // Will increment below
+
+        contribution.refunded = false;
+        contribution.refund_amount = 0;
+        contribution.refunded_at = None;
+        contribution.snapshot_proposal = None;
+        contribution.snapshot_amount = 0;
+        contribution.expired_refund_owed = 0;
+        contribution.expired_refund_debt = 0;
+
+        contribution.bump = contribution_bump;
+    }
+
+    // Amounts added while a proposal is open don't count towards its vote
+    contribution.checkpoint(flow.active_proposal);
+
+    // Keep what's owed from earlier expired milestones before the amount changes
+    contribution.sync_expired_refunds(flow.refund_per_token)?;
+
+    let previous_total = contribution.total_amount;
+
+    // Update the contribution account
+    contribution.total_amount = contribution
+        .total_amount
+        .checked_add(amount)
+        .ok_or(TitaErrors::MathOverflow)?;
+
+    contribution.last_contribution = current_time;
+    contribution.contribution_count = contribution
+        .contribution_count
+        .checked_add(1)
+        .ok_or(TitaErrors::MathOverflow)?;
+
+    contribution.rebase_expired_refunds(flow.refund_per_token)?;
+
+    // Update the flow account
+    let vault_deposits = flow.record_contribution(amount)?;
+    flow.update_voting_weight(previous_total, contribution.total_amount)?;
+
+    // Locked funds go to the milestone vaults, everything else to the flow's token account
+    let vaulted = deposit_into_vaults(
+        flow,
+        &vault_deposits,
+        vault_accounts,
+        contributor_token_account.to_account_info(),
+        contributor.clone(),
+        token_mint,
+        token_program.clone(),
+    )?;
+
+    transfer_checked(
+        CpiContext::new(
+            token_program,
+            anchor_spl::token_interface::TransferChecked {
+                from: contributor_token_account.to_account_info(),
+                mint: token_mint.to_account_info(),
+                to: flow_token_account.to_account_info(),
+                authority: contributor,
+            },
+        ),
+        amount - vaulted,
+        token_mint.decimals,
+    )?;
+
+    Ok(())
+}
+
+// Moves each outstanding milestone's part of a contribution into its vault.
+// `vault_accounts` are (vault, vault token account) pairs in milestone order.
+pub fn deposit_into_vaults<'info>(
+    flow: &mut Account<'info, Flow>,
+    deposits: &[u64],
+    vault_accounts: &'info [AccountInfo<'info>],
+    from: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    token_mint: &InterfaceAccount<'info, Mint>,
+    token_program: AccountInfo<'info>,
+) -> Result<u64> {
+    if !flow.milestone_vaults {
+        return Ok(0);
+    }
+
+    require!(
+        vault_accounts.len() == deposits.len() * 2,
+        TitaErrors::InvalidVaultAccounts
+    );
+
+    let outstanding = flow
+        .milestones
+        .iter()
+        .flatten()
+        .filter(|milestone| !milestone.is_resolved());
+
+    let mut vaulted: u64 = 0;
+
+    for ((milestone, deposit), accounts) in outstanding.zip(deposits).zip(vault_accounts.chunks(2)) {
+        let mut vault = Account::<Vault>::try_from(&accounts[0])?;
+        let vault_token_account = &accounts[1];
+
+        require!(
+            vault.flow == flow.key()
+                && vault.milestone_id == milestone.id
+                && vault.token_account == vault_token_account.key()
+                && vault_token_account.is_writable,
+            TitaErrors::InvalidVaultAccounts
+        );
+
+        if *deposit == 0 {
+            continue;
+        }
+
+        transfer_checked(
+            CpiContext::new(
+                token_program.clone(),
+                anchor_spl::token_interface::TransferChecked {
+                    from: from.clone(),
+                    mint: token_mint.to_account_info(),
+                    to: vault_token_account.clone(),
+                    authority: authority.clone(),
+                },
+            ),
+            *deposit,
+            token_mint.decimals,
+        )?;
+
+        vault.amount = vault
+            .amount
+            .checked_add(*deposit)
+            .ok_or(TitaErrors::MathOverflow)?;
+        vault.exit(&crate::ID)?;
+
+        vaulted = vaulted
+            .checked_add(*deposit)
+            .ok_or(TitaErrors::MathOverflow)?;
+    }
+
+    flow.vault_holdings = flow
+        .vault_holdings
+        .checked_add(vaulted)
+        .ok_or(TitaErrors::MathOverflow)?;
+
+    Ok(vaulted)
+}
+
 // Event emitted when a contribution is made
 #[event]
 pub struct ContributionEvent {
     pub flow: Pubkey,
     pub contributor: Pubkey,
     pub amount: u64,
+    pub excess_returned: u64,
     pub total_contributed: u64,
     pub timestamp: i64,
 }
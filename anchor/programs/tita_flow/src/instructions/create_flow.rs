@@ -1,16 +1,13 @@
 use anchor_lang::prelude::*;
 use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 use crate::{
-    constants::{TITA_FLOW_SEED, TITA_FLOW_TA_SEED}, errors::TitaErrors, states::{Flow, FlowStatus, Milestone, VotingPowerModel}
+    constants::{BASIS_POINTS_DENOMINATOR, MAX_MILESTONES, MAX_PAYOUT_SPLITS, TITA_FLOW_SEED, TITA_FLOW_TA_SEED}, errors::TitaErrors, states::{ApprovalMode, Flow, FlowKind, FlowStatus, FundingMode, ContributionCaps, GovernanceConfig, Milestone, MilestoneInput, PayoutSplit, ReleaseMode, VotingPowerModel, validate_milestone_schedule}
 };
 
 #[derive(Accounts)]
 #[instruction(
     flow_id: String,
     goal: u64,
-    start_time: Option<i64>,
-    end_time: Option<i64>,
-    milestones: Option<Vec<Milestone>>
 )]
 pub struct CreateFlow<'info> {
     #[account(mut)]
@@ -52,14 +49,25 @@ pub struct CreateFlow<'info> {
 }
 
 impl<'info> CreateFlow<'info> {
+    #[allow(clippy::too_many_arguments)]
     pub fn create(
         &mut self,
         flow_id: String,
         goal: u64,
         start_time: Option<i64>,
         end_time: Option<i64>,
-        milestones: Option<Vec<Milestone>>,
+        milestones: Option<Vec<MilestoneInput>>,
         voting_power_model: VotingPowerModel,
+        governance: Option<GovernanceConfig>,
+        kind: FlowKind,
+        funding_mode: FundingMode,
+        release_mode: ReleaseMode,
+        approval_mode: ApprovalMode,
+        sequential_milestones: bool,
+        milestone_vaults: bool,
+        beneficiary: Option<Pubkey>,
+        splits: Option<Vec<PayoutSplit>>,
+        caps: Option<ContributionCaps>,
         flow_bump: u8
     ) -> Result<()> {
         let current_time = Clock::get()?.unix_timestamp;
@@ -86,11 +94,43 @@ impl<'info> CreateFlow<'info> {
             );
         }
         
+        // All-or-nothing flows are settled at their end date
+        if funding_mode == FundingMode::AllOrNothing {
+            require!(
+                end_time.is_some(),
+                TitaErrors::AllOrNothingRequiresEndDate
+            );
+        }
+
+        // Fair launch flows limit the total raised and what each wallet puts in
+        if let Some(caps) = &caps {
+            caps.validate(goal)?;
+        }
+
+        // Voting rules for proposals the program opens, e.g. on milestone submission
+        let governance = governance.unwrap_or_default();
+        governance.validate()?;
+
+        // Payouts go to the creator unless someone else is named
+        let beneficiary = beneficiary.unwrap_or(self.creator.key());
+        require!(
+            beneficiary != Pubkey::default(),
+            TitaErrors::InvalidBeneficiary
+        );
+
+        // A grant needs a donor to fund it and a separate grantee to pay out to
+        if let FlowKind::Distribute { donor } = kind {
+            require!(
+                donor != Pubkey::default() && donor != beneficiary,
+                TitaErrors::InvalidGrantParticipants
+            );
+        }
+
         // Validate milestones if provided
         if let Some(milestones_vec) = &milestones {
             // Ensure we don't exceed maximum milestone count
             require!(
-                milestones_vec.len() <= 10,
+                milestones_vec.len() <= MAX_MILESTONES,
                 TitaErrors::TooManyMilestones
             );
             
@@ -101,6 +141,20 @@ impl<'info> CreateFlow<'info> {
             );
             
             
+            // Milestones can't promise more than the flow is raising
+            let milestone_total = milestones_vec.iter()
+                .try_fold(0u64, |sum, milestone| sum.checked_add(milestone.amount))
+                .ok_or(TitaErrors::MathOverflow)?;
+            require!(
+                milestone_total <= goal,
+                TitaErrors::MilestoneTotalMismatch
+            );
+            
+            // Milestones are listed in delivery order
+            validate_milestone_schedule(
+                milestones_vec.iter().map(|milestone| (milestone.id, milestone.deadline))
+            )?;
+            
             // Validate each milestone
             for milestone in milestones_vec {
                 // Validate milestone amount
@@ -114,23 +168,81 @@ impl<'info> CreateFlow<'info> {
                     milestone.deadline > current_time,
                     TitaErrors::InvalidMilestoneDeadline
                 );
-
-                //ensure milestone withdrawn is false
-                require!(
-                    !milestone.completed,
-                    TitaErrors::MilestoneCantBeCompletedAtCreation
-                );
-                
             }
             
         }
+
+        // Only milestone releases are streamed
+        if release_mode == ReleaseMode::Streaming {
+            require!(
+                milestones.is_some(),
+                TitaErrors::StreamingRequiresMilestones
+            );
+        }
+
+        // Optimistic approval only applies to milestone submissions
+        if let ApprovalMode::Optimistic { challenge_period } = approval_mode {
+            require!(milestones.is_some(), TitaErrors::NotMilestoneFlow);
+            require!(challenge_period > 0, TitaErrors::InvalidChallengePeriod);
+        }
+
+        // Vaults hold contributions' locked part, all-or-nothing flows lock nothing until settled
+        if milestone_vaults {
+            require!(
+                milestones.is_some() && funding_mode == FundingMode::Flexible,
+                TitaErrors::InvalidMilestoneVaults
+            );
+        }
         
+        // Payouts can be shared between several recipients instead of the beneficiary
+        let splits = match splits {
+            Some(mut splits_vec) => {
+                require!(
+                    (2..=MAX_PAYOUT_SPLITS).contains(&splits_vec.len()),
+                    TitaErrors::InvalidSplitRecipients
+                );
+
+                let mut total_basis_points: u32 = 0;
+                for (index, split) in splits_vec.iter().enumerate() {
+                    require!(
+                        split.recipient != Pubkey::default()
+                            && splits_vec[..index].iter().all(|other| other.recipient != split.recipient),
+                        TitaErrors::InvalidSplitRecipients
+                    );
+                    require!(split.basis_points > 0, TitaErrors::InvalidSplitShares);
+
+                    total_basis_points += split.basis_points as u32;
+                }
+                require!(
+                    total_basis_points == BASIS_POINTS_DENOMINATOR as u32,
+                    TitaErrors::InvalidSplitShares
+                );
+
+                // Nothing has been paid out yet
+                for split in splits_vec.iter_mut() {
+                    split.distributed = 0;
+                }
+
+                Some(splits_vec)
+            },
+            None => None,
+        };
+
         // Initialize flow account
         let flow = &mut self.flow;
         flow.flow_id = flow_id;
         flow.creator = self.creator.key();
+        flow.beneficiary = beneficiary;
         flow.token_mint = self.token_mint.key();
+        flow.kind = kind;
+        flow.funding_mode = funding_mode;
+        flow.release_mode = release_mode;
+        flow.approval_mode = approval_mode;
+        flow.sequential_milestones = sequential_milestones;
+        flow.milestone_vaults = milestone_vaults;
+        flow.vault_holdings = 0;
         flow.goal = goal;
+        flow.caps = caps;
         flow.raised = 0;
         flow.balance = 0;   
         flow.available = 0;
@@ -138,13 +250,22 @@ impl<'info> CreateFlow<'info> {
         flow.flow_ta = self.flow_token_account.key();
         flow.start_date = start_time;
         flow.end_date = end_time;
-        flow.flow_status = FlowStatus::Active;
+        // Flows with a future start date stay pending until they open
+        flow.flow_status = match start_time {
+            Some(start) if start > current_time => FlowStatus::Pending,
+            _ => FlowStatus::Active,
+        };
         flow.contributor_count = 0;
-        flow.milestones = milestones;
+        flow.milestones = milestones
+            .map(|milestones_vec| milestones_vec.into_iter().map(Milestone::from).collect());
+        flow.splits = splits;
         flow.proposal_count = 0;
         flow.voting_power_model = voting_power_model;
+        flow.governance = governance;
         flow.active_proposal = None;
+        flow.total_voting_weight = 0;
         flow.total_refunded = 0;
+        flow.refunded_contributions = 0;
         flow.refunds_count = 0;
         flow.bump = flow_bump;
         
@@ -152,8 +273,17 @@ impl<'info> CreateFlow<'info> {
         emit!(FlowCreatedEvent {
             flow_id: flow.flow_id.clone(),
             creator: flow.creator,
+            beneficiary,
+            kind,
+            funding_mode,
+            release_mode,
+            approval_mode,
+            sequential_milestones,
+            milestone_vaults,
             is_milestone: flow.milestones.is_some(),
+            has_splits: flow.splits.is_some(),
             goal,
+            hard_cap: caps.map(|caps| caps.hard_cap.unwrap_or(goal)),
             timestamp: current_time,
         });
         
@@ -166,16 +296,16 @@ impl<'info> CreateFlow<'info> {
 pub struct FlowCreatedEvent {
     pub flow_id: String,
     pub creator: Pubkey,
+    pub beneficiary: Pubkey,
+    pub kind: FlowKind,
+    pub funding_mode: FundingMode,
+    pub release_mode: ReleaseMode,
+    pub approval_mode: ApprovalMode,
+    pub sequential_milestones: bool,
+    pub milestone_vaults: bool,
     pub is_milestone: bool,
+    pub has_splits: bool,
     pub goal: u64,
+    pub hard_cap: Option<u64>,
     pub timestamp: i64,
 }
-
-// For event use
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub enum FlowType {
-    Direct,
-    Milestone,
-    Weighted
-}
-
@@ -1,11 +1,16 @@
 use anchor_lang::prelude::*;
 
 use crate::{
-    constants::TITA_PROPOSAL_SEED,
+    constants::{TITA_CONTRIBUTION_SEED, TITA_PROPOSAL_SEED},
     errors::TitaErrors,
-    states::{Flow, Proposal, ProposalStatus, ProposalType},
+    states::{Contribution, Flow, GovernanceConfig, Proposal, ProposalStatus, ProposalType},
 };
 
+/// Opens a proposal on a flow
+///
+/// Only contributors can propose, on grants only the donor and the creator.
+/// The voting rules can be stricter than the flow's governance settings but
+/// never looser.
 #[derive(Accounts)]
 pub struct CreateProposal<'info> {
     #[account(mut)]
@@ -13,10 +18,23 @@ pub struct CreateProposal<'info> {
 
     #[account(
         mut,
-        constraint = flow.active_proposal.is_none() @ TitaErrors::ActiveProposalExists
+        constraint = flow.active_proposal.is_none() @ TitaErrors::ActiveProposalExists,
+        constraint = flow.proposal_count < u8::MAX @ TitaErrors::MathOverflow
     )]
     pub flow: Account<'info, Flow>,
 
+    #[account(
+        seeds = [
+            TITA_CONTRIBUTION_SEED,
+            flow.key().as_ref(),
+            proposer.key().as_ref()
+        ],
+        bump = contribution.bump,
+        constraint = contribution.total_amount > 0 @ TitaErrors::UnauthorizedContributor,
+        constraint = !contribution.refunded @ TitaErrors::AlreadyRefunded,
+    )]
+    pub contribution: Option<Account<'info, Contribution>>,
+
     #[account(
         init,
         payer = proposer,
@@ -24,7 +42,8 @@ pub struct CreateProposal<'info> {
         seeds = [
             TITA_PROPOSAL_SEED,
             flow.key().as_ref(),
-            &[flow.proposal_count.checked_add(1).unwrap()]
+            // Never actually wraps, the flow constraint fails once the u8 count is used up
+            &[flow.proposal_count.wrapping_add(1)]
         ],
         bump
     )]
@@ -42,30 +61,84 @@ impl<'info> CreateProposal<'info> {
         approval_percentage: u16,
         bump: u8,
     ) -> Result<()> {
-        let flow = &mut self.flow;
-        let proposal = &mut self.proposal;
+        let governance = GovernanceConfig {
+            voting_period: voting_duration,
+            quorum_percentage,
+            approval_percentage,
+        };
+        match self.flow.donor() {
+            Some(donor) => require!(
+                self.proposer.key() == donor || self.proposer.key() == self.flow.creator,
+                TitaErrors::UnauthorizedProposer
+            ),
+            None => require!(self.contribution.is_some(), TitaErrors::UnauthorizedContributor),
+        }
 
-        // Initialize proposal
-        proposal.flow = flow.key();
-        proposal.proposer = self.proposer.key();
-        proposal.proposal_type = proposal_type;
-        proposal.status = ProposalStatus::Active;
-        proposal.created_at = Clock::get()?.unix_timestamp;
-        proposal.voting_ends_at = proposal.created_at + voting_duration;
-        proposal.votes_for = 0;
-        proposal.votes_against = 0;
-        proposal.votes_abstain = 0;
-        proposal.total_eligible_votes = flow.raised;
-        proposal.executed_at = None;
-        proposal.quorum_percentage = quorum_percentage;
-        proposal.approval_percentage = approval_percentage;
-        proposal.last_vote_check = proposal.created_at;
-        proposal.bump = bump;
+        governance.validate()?;
+        governance.validate_against(&self.flow.governance)?;
 
-        // Update flow with active proposal
-        flow.active_proposal = Some(proposal.key());
-        flow.proposal_count = flow.proposal_count.checked_add(1).unwrap();
+        // No point voting on a milestone that can't be released yet
+        if self.flow.sequential_milestones {
+            if let ProposalType::MilestoneCompletion { milestone_id }
+            | ProposalType::PartialMilestoneRelease { milestone_id, .. } = proposal_type
+            {
+                self.flow.check_previous_milestones_resolved(milestone_id)?;
+            }
+        }
 
-        Ok(())
+        open_proposal(
+            &mut self.flow,
+            &mut self.proposal,
+            self.proposer.key(),
+            proposal_type,
+            governance,
+            bump,
+        )
     }
 }
+
+/// Initializes a freshly created proposal account and makes it the flow's
+/// active proposal
+pub fn open_proposal(
+    flow: &mut Account<Flow>,
+    proposal: &mut Account<Proposal>,
+    proposer: Pubkey,
+    proposal_type: ProposalType,
+    governance: GovernanceConfig,
+    bump: u8,
+) -> Result<()> {
+    let clock = Clock::get()?;
+
+    // No governance before the flow has opened
+    flow.activate_if_started(clock.unix_timestamp)?;
+
+    // Initialize proposal
+    proposal.flow = flow.key();
+    proposal.proposer = proposer;
+    proposal.proposal_type = proposal_type;
+    proposal.status = ProposalStatus::Active;
+    proposal.created_at = clock.unix_timestamp;
+    proposal.voting_starts_at = proposal.created_at;
+    proposal.voting_ends_at = proposal.created_at + governance.voting_period;
+    proposal.votes_for = 0;
+    proposal.votes_against = 0;
+    proposal.votes_abstain = 0;
+    // Voting power is frozen here, later contributions don't count towards this proposal
+    proposal.total_eligible_votes = flow.total_voting_weight;
+    proposal.snapshot_slot = clock.slot;
+    proposal.snapshot_timestamp = clock.unix_timestamp;
+    proposal.executed_at = None;
+    proposal.quorum_percentage = governance.quorum_percentage;
+    proposal.approval_percentage = governance.approval_percentage;
+    proposal.last_vote_check = proposal.created_at;
+    proposal.bump = bump;
+
+    // Update flow with active proposal
+    flow.active_proposal = Some(proposal.key());
+    flow.proposal_count = flow
+        .proposal_count
+        .checked_add(1)
+        .ok_or(TitaErrors::MathOverflow)?;
+
+    Ok(())
+}
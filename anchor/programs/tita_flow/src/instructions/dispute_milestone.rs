@@ -0,0 +1,108 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    constants::{TITA_CONTRIBUTION_SEED, TITA_PROPOSAL_SEED},
+    errors::TitaErrors,
+    instructions::open_proposal,
+    states::{ApprovalMode, Contribution, Flow, FlowStatus, MilestoneStatus, Proposal, ProposalType},
+};
+
+/// Challenges an optimistic milestone submission
+///
+/// Any contributor can dispute while the challenge window is open. The window
+/// is closed and the milestone goes through a regular MilestoneCompletion
+/// vote using the flow's governance settings.
+#[derive(Accounts)]
+pub struct DisputeMilestone<'info> {
+    #[account(mut)]
+    pub challenger: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = flow.flow_status != FlowStatus::Canceled @ TitaErrors::FlowCanceled,
+        constraint = flow.active_proposal.is_none() @ TitaErrors::ActiveProposalExists,
+        constraint = flow.proposal_count < u8::MAX @ TitaErrors::MathOverflow
+    )]
+    pub flow: Account<'info, Flow>,
+
+    #[account(
+        seeds = [
+            TITA_CONTRIBUTION_SEED,
+            flow.key().as_ref(),
+            challenger.key().as_ref()
+        ],
+        bump = contribution.bump,
+        constraint = contribution.total_amount > 0 @ TitaErrors::UnauthorizedContributor,
+        constraint = !contribution.refunded @ TitaErrors::AlreadyRefunded,
+    )]
+    pub contribution: Account<'info, Contribution>,
+
+    #[account(
+        init,
+        payer = challenger,
+        space = 8 + Proposal::INIT_SPACE,
+        seeds = [
+            TITA_PROPOSAL_SEED,
+            flow.key().as_ref(),
+            &[flow.proposal_count.wrapping_add(1)]
+        ],
+        bump
+    )]
+    pub proposal: Account<'info, Proposal>,
+
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> DisputeMilestone<'info> {
+    pub fn dispute_milestone(&mut self, milestone_id: u32, proposal_bump: u8) -> Result<()> {
+        require!(
+            matches!(self.flow.approval_mode, ApprovalMode::Optimistic { .. }),
+            TitaErrors::NotOptimisticFlow
+        );
+
+        let current_time = Clock::get()?.unix_timestamp;
+
+        let milestone = self.flow.milestone_mut(milestone_id)?;
+
+        require!(milestone.status == MilestoneStatus::Submitted, TitaErrors::MilestoneNotSubmitted);
+
+        let challenge_ends_at = milestone.challenge_ends_at.ok_or(TitaErrors::ChallengeWindowClosed)?;
+        require!(current_time <= challenge_ends_at, TitaErrors::ChallengeWindowClosed);
+
+        // From here on only the vote can approve the milestone
+        milestone.challenge_ends_at = None;
+
+        let governance = self.flow.governance;
+
+        open_proposal(
+            &mut self.flow,
+            &mut self.proposal,
+            self.challenger.key(),
+            ProposalType::MilestoneCompletion { milestone_id },
+            governance,
+            proposal_bump,
+        )?;
+
+        emit!(MilestoneDisputedEvent {
+            flow: self.flow.key(),
+            milestone_id,
+            challenger: self.challenger.key(),
+            proposal: self.proposal.key(),
+            voting_ends_at: self.proposal.voting_ends_at,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+}
+
+// Event emitted when a contributor disputes a milestone submission
+#[event]
+pub struct MilestoneDisputedEvent {
+    pub flow: Pubkey,
+    pub milestone_id: u32,
+    pub challenger: Pubkey,
+    pub proposal: Pubkey,
+    pub voting_ends_at: i64,
+    pub timestamp: i64,
+}
@@ -0,0 +1,130 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked};
+
+use crate::{
+    errors::TitaErrors,
+    states::{Flow, FlowStatus},
+};
+
+/// Pays a flow's available funds out to its split recipients
+///
+/// Takes the place of withdraw on flows created with payout splits. The
+/// recipients' token accounts are passed as remaining accounts in the same
+/// order as the splits. Destinations are fixed by the flow, so anyone can crank it.
+#[derive(Accounts)]
+pub struct DistributeSplits<'info> {
+    pub distributor: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = flow.flow_status != FlowStatus::Canceled @ TitaErrors::FlowCanceled,
+        constraint = flow.splits.is_some() @ TitaErrors::NoPayoutSplits,
+        constraint = flow.token_mint == token_mint.key() @ TitaErrors::InvalidTokenMint,
+    )]
+    pub flow: Account<'info, Flow>,
+
+    #[account(
+        mut,
+        constraint = flow_token_account.key() == flow.flow_ta @ TitaErrors::InvalidFlowTokenAccount,
+    )]
+    pub flow_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> DistributeSplits<'info> {
+    pub fn distribute(&mut self, recipient_accounts: &'info [AccountInfo<'info>]) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+
+        // Bring streamed milestone releases up to date
+        self.flow.vest_streams(current_time)?;
+
+        let amount = self.flow.available;
+        require!(amount > 0, TitaErrors::InsufficientFunds);
+
+        // Every recipient's token account must line up with its split
+        let splits = self.flow.splits.as_ref().ok_or(TitaErrors::NoPayoutSplits)?;
+        require!(
+            recipient_accounts.len() == splits.len(),
+            TitaErrors::InvalidSplitAccounts
+        );
+
+        for (split, account_info) in splits.iter().zip(recipient_accounts) {
+            let recipient_token_account = InterfaceAccount::<TokenAccount>::try_from(account_info)?;
+
+            require!(account_info.is_writable, TitaErrors::InvalidSplitAccounts);
+            require!(
+                recipient_token_account.owner == split.recipient,
+                TitaErrors::InvalidSplitAccounts
+            );
+            require!(
+                recipient_token_account.mint == self.flow.token_mint,
+                TitaErrors::InvalidTokenMint
+            );
+        }
+
+        let payouts = self.flow.allocate_distribution(amount)?;
+
+        let flow_seeds = self.flow.signer_seeds();
+        let seeds: &[&[u8]] = &flow_seeds;
+
+        let signer_seeds = &[seeds];
+
+        for (payout, account_info) in payouts.iter().zip(recipient_accounts) {
+            // Small payouts can round to nothing for a recipient, they catch up next time
+            if *payout == 0 {
+                continue;
+            }
+
+            transfer_checked(
+                CpiContext::new_with_signer(
+                    self.token_program.to_account_info(),
+                    TransferChecked {
+                        from: self.flow_token_account.to_account_info(),
+                        mint: self.token_mint.to_account_info(),
+                        to: account_info.clone(),
+                        authority: self.flow.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                *payout,
+                self.token_mint.decimals,
+            )?;
+        }
+
+        let flow = &mut self.flow;
+
+        flow.available = 0;
+        flow.withdrawn = flow
+            .withdrawn
+            .checked_add(amount)
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        flow.check_balances()?;
+
+        emit!(FundsDistributedEvent {
+            flow: flow.key(),
+            amount,
+            recipients: flow
+                .splits
+                .as_ref()
+                .map(|splits| splits.iter().map(|split| split.recipient).collect())
+                .unwrap_or_default(),
+            payouts,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+}
+
+// Event emitted when available funds are paid out to the split recipients
+#[event]
+pub struct FundsDistributedEvent {
+    pub flow: Pubkey,
+    pub amount: u64,
+    pub recipients: Vec<Pubkey>,
+    pub payouts: Vec<u64>,
+    pub timestamp: i64,
+}
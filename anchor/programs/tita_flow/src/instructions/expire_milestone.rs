@@ -0,0 +1,99 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+use crate::{
+    errors::TitaErrors,
+    instructions::release_vaults,
+    states::{Flow, Proposal, ProposalType},
+};
+
+/// Expires a milestone that missed its deadline
+///
+/// Anyone can crank this once the deadline has passed without the milestone
+/// being completed. Its share of the locked balance goes into the refund pool
+/// that contributors claim through refund, the rest of the flow carries on.
+/// A proposal still open about the milestone, e.g. to extend its deadline,
+/// has to be resolved first and must be passed in when one is active. On flows
+/// with milestone vaults the refunded funds are moved out of the milestone's
+/// vault into flow_ta, which refunds are paid from.
+#[derive(Accounts)]
+pub struct ExpireMilestone<'info> {
+    pub cranker: Signer<'info>,
+
+    #[account(mut)]
+    pub flow: Account<'info, Flow>,
+
+    #[account(
+        constraint = Some(active_proposal.key()) == flow.active_proposal @ TitaErrors::InvalidProposal,
+    )]
+    pub active_proposal: Option<Account<'info, Proposal>>,
+
+    // Flows with milestone vaults pass flow_ta, the mint and the token program,
+    // with the expired milestone's vault and vault token account as remaining accounts
+    #[account(
+        mut,
+        constraint = flow_token_account.key() == flow.flow_ta @ TitaErrors::InvalidFlowTokenAccount,
+    )]
+    pub flow_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        constraint = token_mint.key() == flow.token_mint @ TitaErrors::InvalidTokenMint,
+    )]
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+impl<'info> ExpireMilestone<'info> {
+    pub fn expire_milestone(
+        &mut self,
+        milestone_id: u32,
+        vault_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+
+        if self.flow.active_proposal.is_some() {
+            let proposal = self.active_proposal.as_ref().ok_or(TitaErrors::InvalidProposal)?;
+
+            let about_milestone = match proposal.proposal_type {
+                ProposalType::MilestoneCompletion { milestone_id: id }
+                | ProposalType::MilestoneAdjustment { milestone_id: id, .. }
+                | ProposalType::PartialMilestoneRelease { milestone_id: id, .. }
+                | ProposalType::RemoveMilestone { milestone_id: id } => id == milestone_id,
+                _ => false,
+            };
+            require!(!about_milestone, TitaErrors::MilestoneProposalActive);
+        }
+
+        let refunded = self.flow.expire_milestone(milestone_id, current_time)?;
+
+        release_vaults(
+            &mut self.flow,
+            vault_accounts,
+            self.flow_token_account.as_ref(),
+            self.token_mint.as_ref(),
+            self.token_program.as_ref(),
+        )?;
+
+        self.flow.check_balances()?;
+
+        emit!(MilestoneExpiredEvent {
+            flow: self.flow.key(),
+            milestone_id,
+            refunded,
+            refund_pool: self.flow.refund_pool,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+}
+
+// Event emitted when a missed milestone's funds are handed back to contributors
+#[event]
+pub struct MilestoneExpiredEvent {
+    pub flow: Pubkey,
+    pub milestone_id: u32,
+    pub refunded: u64,
+    pub refund_pool: u64,
+    pub timestamp: i64,
+}
@@ -0,0 +1,106 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+use crate::{
+    errors::TitaErrors,
+    instructions::{execute_or_fail_proposal, release_vaults},
+    states::{Flow, Proposal, ProposalStatus},
+};
+
+/// Resolves a proposal once its voting period is over
+///
+/// Anyone can crank this instruction. The final tally decides whether the
+/// proposal passed; passed proposals are executed right away. Grant proposals
+/// only pass on the donor's For vote, which executes them as it's cast, so
+/// any still open here have failed. The flow's active proposal is always
+/// cleared so new proposals can be created. Passed proposals that release funds
+/// from milestone vaults need those vaults passed in, the funds are moved to
+/// flow_ta as the proposal executes.
+#[derive(Accounts)]
+pub struct FinalizeProposal<'info> {
+    pub cranker: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = proposal.status == ProposalStatus::Active @ TitaErrors::ProposalNotActive,
+    )]
+    pub proposal: Account<'info, Proposal>,
+
+    #[account(
+        mut,
+        constraint = flow.key() == proposal.flow @ TitaErrors::InvalidFlow,
+        constraint = flow.active_proposal == Some(proposal.key()) @ TitaErrors::InvalidProposal
+    )]
+    pub flow: Account<'info, Flow>,
+
+    // Flows with milestone vaults pass flow_ta, the mint and the token program,
+    // with the vaults the proposal releases from as remaining accounts
+    #[account(
+        mut,
+        constraint = flow_token_account.key() == flow.flow_ta @ TitaErrors::InvalidFlowTokenAccount,
+    )]
+    pub flow_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        constraint = token_mint.key() == flow.token_mint @ TitaErrors::InvalidTokenMint,
+    )]
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+impl<'info> FinalizeProposal<'info> {
+    pub fn finalize_proposal(&mut self, vault_accounts: &'info [AccountInfo<'info>]) -> Result<()> {
+        let proposal = &mut self.proposal;
+        let flow = &mut self.flow;
+
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(current_time > proposal.voting_ends_at, TitaErrors::VotingStillActive);
+
+        // Resolve the final tally into Passed or Failed
+        if flow.donor().is_some() {
+            proposal.status = ProposalStatus::Failed;
+        } else {
+            proposal.update_status()?;
+        }
+
+        if proposal.can_execute() {
+            execute_or_fail_proposal(proposal, flow, current_time);
+        }
+
+        flow.close_active_proposal(proposal.created_at, current_time);
+
+        release_vaults(
+            flow,
+            vault_accounts,
+            self.flow_token_account.as_ref(),
+            self.token_mint.as_ref(),
+            self.token_program.as_ref(),
+        )?;
+
+        flow.check_balances()?;
+
+        emit!(ProposalFinalizedEvent {
+            flow: flow.key(),
+            proposal: proposal.key(),
+            status: proposal.status,
+            votes_for: proposal.votes_for,
+            votes_against: proposal.votes_against,
+            votes_abstain: proposal.votes_abstain,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+}
+
+// Event emitted when an expired proposal is resolved
+#[event]
+pub struct ProposalFinalizedEvent {
+    pub flow: Pubkey,
+    pub proposal: Pubkey,
+    pub status: ProposalStatus,
+    pub votes_for: u64,
+    pub votes_against: u64,
+    pub votes_abstain: u64,
+    pub timestamp: i64,
+}
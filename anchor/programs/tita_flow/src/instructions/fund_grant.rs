@@ -0,0 +1,128 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+use crate::{
+    constants::TITA_CONTRIBUTION_SEED,
+    errors::TitaErrors,
+    instructions::deposit_contribution,
+    states::{Contribution, Flow},
+};
+
+/// Funds a DISTRIBUTE flow
+///
+/// Grant flows have a single donor, so this is the only way tokens enter them.
+/// The donor's deposits are recorded in a regular contribution account which
+/// also gives them the vote on milestone releases. Like contribute, flows with
+/// milestone vaults take the outstanding milestones' vaults as remaining accounts.
+#[derive(Accounts)]
+pub struct FundGrant<'info> {
+    #[account(mut)]
+    pub donor: Signer<'info>,
+
+    #[account(mut,
+        constraint = flow.is_open() @ TitaErrors::FlowNotActive,
+        constraint = flow.donor().is_some() @ TitaErrors::NotGrantFlow,
+        constraint = flow.donor() == Some(donor.key()) @ TitaErrors::UnauthorizedDonor,
+        constraint = flow.token_mint == token_mint.key() @ TitaErrors::InvalidTokenMint,
+    )]
+    pub flow: Account<'info, Flow>,
+
+    #[account(
+        init_if_needed,
+        payer = donor,
+        space = 8 + Contribution::INIT_SPACE,
+        seeds = [
+            TITA_CONTRIBUTION_SEED,
+            flow.key().as_ref(),
+            donor.key().as_ref()
+        ],
+        bump
+    )]
+    pub contribution: Account<'info, Contribution>,
+
+    #[account(
+        mut,
+        constraint = donor_token_account.mint == token_mint.key() @ TitaErrors::InvalidTokenAccount,
+        constraint = donor_token_account.owner == donor.key() @ TitaErrors::InvalidTokenOwner,
+    )]
+    pub donor_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = flow_token_account.key() == flow.flow_ta @ TitaErrors::InvalidFlowTokenAccount,
+    )]
+    pub flow_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> FundGrant<'info> {
+    pub fn fund_grant(
+        &mut self,
+        amount: u64,
+        contribution_bump: u8,
+        vault_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        require!(amount > 0, TitaErrors::InvalidContributionAmount);
+
+        let current_timestamp = Clock::get()?.unix_timestamp;
+
+        if let Some(end_date) = self.flow.end_date {
+            require!(current_timestamp <= end_date, TitaErrors::FlowEnded);
+        }
+
+        self.flow.activate_if_started(current_timestamp)?;
+
+        // Grants are capped the same way as contributions
+        let requested = amount;
+        let amount = self
+            .flow
+            .accepted_contribution(requested, self.contribution.total_amount)?;
+
+        // Move the grant into the milestone vaults and the flow treasury
+        deposit_contribution(
+            &mut self.flow,
+            &mut self.contribution,
+            amount,
+            contribution_bump,
+            current_timestamp,
+            vault_accounts,
+            self.donor.to_account_info(),
+            &self.donor_token_account,
+            &self.flow_token_account,
+            &self.token_mint,
+            self.token_program.to_account_info(),
+        )?;
+
+        // The donor is the only contributor a grant ever has
+        if self.contribution.contribution_count == 1 {
+            self.flow.contributor_count = 1;
+        }
+
+        self.flow.check_balances()?;
+
+        emit!(GrantFundedEvent {
+            flow: self.flow.key(),
+            donor: self.donor.key(),
+            amount,
+            excess_returned: requested - amount,
+            total_funded: self.contribution.total_amount,
+            timestamp: current_timestamp,
+        });
+
+        Ok(())
+    }
+}
+
+// Event emitted when the donor funds a grant
+#[event]
+pub struct GrantFundedEvent {
+    pub flow: Pubkey,
+    pub donor: Pubkey,
+    pub amount: u64,
+    pub excess_returned: u64,
+    pub total_funded: u64,
+    pub timestamp: i64,
+}
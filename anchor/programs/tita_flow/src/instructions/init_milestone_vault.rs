@@ -0,0 +1,98 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+use crate::{
+    constants::{TITA_VAULT_SEED, TITA_VAULT_TA_SEED},
+    errors::TitaErrors,
+    states::{Flow, Vault},
+};
+
+/// Opens the vault holding a milestone's locked funds
+///
+/// Flows created with milestone vaults need one for every outstanding
+/// milestone before they can take contributions, including milestones added
+/// later by proposal. The token account is owned by the flow PDA like flow_ta.
+/// Anyone can pay for it.
+#[derive(Accounts)]
+#[instruction(milestone_id: u32)]
+pub struct InitMilestoneVault<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        constraint = flow.milestone_vaults @ TitaErrors::NotVaultFlow,
+        constraint = flow.token_mint == token_mint.key() @ TitaErrors::InvalidTokenMint,
+    )]
+    pub flow: Account<'info, Flow>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Vault::INIT_SPACE,
+        seeds = [
+            TITA_VAULT_SEED,
+            flow.key().as_ref(),
+            &milestone_id.to_le_bytes()
+        ],
+        bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init,
+        payer = payer,
+        seeds = [
+            TITA_VAULT_TA_SEED,
+            vault.key().as_ref()
+        ],
+        bump,
+        token::mint = token_mint,
+        token::authority = flow,
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> InitMilestoneVault<'info> {
+    pub fn init_milestone_vault(&mut self, milestone_id: u32, vault_bump: u8) -> Result<()> {
+        let milestone = self
+            .flow
+            .milestones
+            .iter()
+            .flatten()
+            .find(|milestone| milestone.id == milestone_id)
+            .ok_or(TitaErrors::MilestoneNotFound)?;
+
+        let vault = &mut self.vault;
+        vault.flow = self.flow.key();
+        vault.milestone_id = milestone_id;
+        vault.token_account = self.vault_token_account.key();
+        vault.amount = 0;
+        vault.milestone_deadline = Some(milestone.deadline);
+        vault.milestone_completed = Some(milestone.is_completed());
+        vault.bump = vault_bump;
+
+        emit!(MilestoneVaultCreatedEvent {
+            flow: vault.flow,
+            milestone_id,
+            vault: vault.key(),
+            token_account: vault.token_account,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+}
+
+// Event emitted when a milestone's vault is opened
+#[event]
+pub struct MilestoneVaultCreatedEvent {
+    pub flow: Pubkey,
+    pub milestone_id: u32,
+    pub vault: Pubkey,
+    pub token_account: Pubkey,
+    pub timestamp: i64,
+}
@@ -4,6 +4,9 @@ pub use create_flow::*;
 pub mod contribute;
 pub use contribute::*;
 
+pub mod fund_grant;
+pub use fund_grant::*;
+
 pub mod withdraw;
 pub use withdraw::*;
 
@@ -14,4 +17,40 @@ pub mod create_proposal;
 pub use create_proposal::*;
 
 pub mod vote_on_proposal;
-pub use vote_on_proposal::*;
\ No newline at end of file
+pub use vote_on_proposal::*;
+
+pub mod finalize_proposal;
+pub use finalize_proposal::*;
+
+pub mod cancel_proposal;
+pub use cancel_proposal::*;
+
+pub mod settle_flow;
+pub use settle_flow::*;
+
+pub mod close_flow;
+pub use close_flow::*;
+
+pub mod cancel_flow;
+pub use cancel_flow::*;
+
+pub mod distribute;
+pub use distribute::*;
+
+pub mod submit_milestone;
+pub use submit_milestone::*;
+
+pub mod dispute_milestone;
+pub use dispute_milestone::*;
+
+pub mod release_milestone;
+pub use release_milestone::*;
+
+pub mod expire_milestone;
+pub use expire_milestone::*;
+
+pub mod init_milestone_vault;
+pub use init_milestone_vault::*;
+
+pub mod sync_vault;
+pub use sync_vault::*;
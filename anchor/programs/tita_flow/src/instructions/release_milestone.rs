@@ -0,0 +1,89 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+use crate::{
+    errors::TitaErrors,
+    instructions::release_vaults,
+    states::{ApprovalMode, Flow, MilestoneStatus},
+};
+
+/// Approves an optimistic milestone nobody disputed
+///
+/// Anyone can crank this once the challenge window is over. The milestone's
+/// funds are released exactly as a passed MilestoneCompletion vote would.
+/// Nothing is released while a proposal is open, it blocks disputes and the
+/// window is extended by the time it was open. On flows with milestone vaults
+/// the released funds are moved out of the milestone's vault into flow_ta.
+#[derive(Accounts)]
+pub struct ReleaseMilestone<'info> {
+    pub cranker: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = matches!(flow.approval_mode, ApprovalMode::Optimistic { .. }) @ TitaErrors::NotOptimisticFlow,
+        constraint = flow.active_proposal.is_none() @ TitaErrors::ActiveProposalExists,
+    )]
+    pub flow: Account<'info, Flow>,
+
+    // Flows with milestone vaults pass flow_ta, the mint and the token program,
+    // with the released milestone's vault and vault token account as remaining accounts
+    #[account(
+        mut,
+        constraint = flow_token_account.key() == flow.flow_ta @ TitaErrors::InvalidFlowTokenAccount,
+    )]
+    pub flow_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        constraint = token_mint.key() == flow.token_mint @ TitaErrors::InvalidTokenMint,
+    )]
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+impl<'info> ReleaseMilestone<'info> {
+    pub fn release_milestone(
+        &mut self,
+        milestone_id: u32,
+        vault_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+
+        let milestone = self.flow.milestone_mut(milestone_id)?;
+
+        require!(milestone.status == MilestoneStatus::Submitted, TitaErrors::MilestoneNotSubmitted);
+
+        // Disputed milestones have no window left, the vote decides them
+        let challenge_ends_at = milestone.challenge_ends_at.ok_or(TitaErrors::MilestoneNotSubmitted)?;
+        require!(current_time > challenge_ends_at, TitaErrors::ChallengeWindowActive);
+
+        let released = self.flow.complete_milestone(milestone_id, current_time)?;
+
+        release_vaults(
+            &mut self.flow,
+            vault_accounts,
+            self.flow_token_account.as_ref(),
+            self.token_mint.as_ref(),
+            self.token_program.as_ref(),
+        )?;
+
+        self.flow.check_balances()?;
+
+        emit!(MilestoneReleasedEvent {
+            flow: self.flow.key(),
+            milestone_id,
+            released,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+}
+
+// Event emitted when an undisputed milestone is released
+#[event]
+pub struct MilestoneReleasedEvent {
+    pub flow: Pubkey,
+    pub milestone_id: u32,
+    pub released: u64,
+    pub timestamp: i64,
+}
@@ -0,0 +1,74 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    errors::TitaErrors,
+    states::{Flow, FlowStatus, FundingMode},
+};
+
+/// Settles an all-or-nothing flow after its end date
+///
+/// Anyone can call this. If the goal was reached the flow is Completed and
+/// its funds are released the same way a flexible flow releases them,
+/// otherwise the flow is Canceled and contributors can claim a full refund.
+#[derive(Accounts)]
+pub struct SettleFlow<'info> {
+    pub settler: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = flow.funding_mode == FundingMode::AllOrNothing @ TitaErrors::NotAllOrNothingFlow,
+        constraint = flow.is_open() @ TitaErrors::FlowNotActive,
+    )]
+    pub flow: Account<'info, Flow>,
+}
+
+impl<'info> SettleFlow<'info> {
+    pub fn settle_flow(&mut self) -> Result<()> {
+        let flow = &mut self.flow;
+        let current_time = Clock::get()?.unix_timestamp;
+
+        let end_date = flow.end_date.ok_or(TitaErrors::AllOrNothingRequiresEndDate)?;
+        require!(current_time > end_date, TitaErrors::FlowNotEnded);
+
+        let succeeded = flow.raised >= flow.goal;
+
+        if succeeded {
+            // Release what isn't reserved for milestones
+            let unlocked = flow.unlocked_share(flow.raised)?;
+
+            flow.balance = flow.balance
+                .checked_sub(unlocked)
+                .ok_or(TitaErrors::MathOverflow)?;
+            flow.available = flow.available
+                .checked_add(unlocked)
+                .ok_or(TitaErrors::MathOverflow)?;
+
+            flow.flow_status = FlowStatus::Completed;
+        } else {
+            // Goal missed, contributors get everything back
+            flow.flow_status = FlowStatus::Canceled;
+        }
+
+        flow.check_balances()?;
+
+        emit!(FlowSettledEvent {
+            flow: flow.key(),
+            goal: flow.goal,
+            raised: flow.raised,
+            succeeded,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+}
+
+// Event emitted when an all-or-nothing flow is settled
+#[event]
+pub struct FlowSettledEvent {
+    pub flow: Pubkey,
+    pub goal: u64,
+    pub raised: u64,
+    pub succeeded: bool,
+    pub timestamp: i64,
+}
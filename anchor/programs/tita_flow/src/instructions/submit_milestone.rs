@@ -0,0 +1,136 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    constants::{MAX_EVIDENCE_URI_LENGTH, TITA_PROPOSAL_SEED},
+    errors::TitaErrors,
+    instructions::open_proposal,
+    states::{ApprovalMode, Flow, FlowStatus, MilestoneStatus, Proposal, ProposalType},
+};
+
+/// Creator's claim that a milestone has been delivered
+///
+/// Stores the evidence on the milestone. Governance flows open the
+/// MilestoneCompletion proposal with the flow's governance settings so
+/// contributors can vote on it straight away, optimistic flows start the
+/// challenge window instead and don't take a proposal account.
+/// A milestone whose vote failed can be submitted again.
+#[derive(Accounts)]
+pub struct SubmitMilestone<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    // An open vote, e.g. on a disputed submission, has to be resolved first
+    #[account(
+        mut,
+        constraint = flow.creator == creator.key() @ TitaErrors::InvalidCreator,
+        constraint = flow.flow_status != FlowStatus::Canceled @ TitaErrors::FlowCanceled,
+        constraint = flow.active_proposal.is_none() @ TitaErrors::ActiveProposalExists,
+        constraint = flow.proposal_count < u8::MAX @ TitaErrors::MathOverflow
+    )]
+    pub flow: Account<'info, Flow>,
+
+    #[account(
+        init,
+        payer = creator,
+        space = 8 + Proposal::INIT_SPACE,
+        seeds = [
+            TITA_PROPOSAL_SEED,
+            flow.key().as_ref(),
+            &[flow.proposal_count.wrapping_add(1)]
+        ],
+        bump
+    )]
+    pub proposal: Option<Account<'info, Proposal>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> SubmitMilestone<'info> {
+    pub fn submit_milestone(
+        &mut self,
+        milestone_id: u32,
+        evidence_uri: String,
+        evidence_hash: [u8; 32],
+        proposal_bump: Option<u8>,
+    ) -> Result<()> {
+        require!(
+            !evidence_uri.is_empty() && evidence_uri.len() <= MAX_EVIDENCE_URI_LENGTH,
+            TitaErrors::InvalidEvidenceUri
+        );
+
+        let current_time = Clock::get()?.unix_timestamp;
+        let approval_mode = self.flow.approval_mode;
+
+        // No point voting on a milestone that can't be completed yet
+        if self.flow.sequential_milestones {
+            self.flow.check_previous_milestones_resolved(milestone_id)?;
+        }
+
+        let milestone = self.flow.milestone_mut(milestone_id)?;
+
+        require!(!milestone.is_completed(), TitaErrors::MilestoneAlreadyCompleted);
+        require!(milestone.status != MilestoneStatus::Expired, TitaErrors::MilestoneExpired);
+
+        milestone.status = MilestoneStatus::Submitted;
+        milestone.evidence_uri = evidence_uri.clone();
+        milestone.evidence_hash = evidence_hash;
+        milestone.submitted_at = Some(current_time);
+        milestone.challenge_ends_at = None;
+
+        let (proposal, voting_ends_at, challenge_ends_at) = match approval_mode {
+            ApprovalMode::Governance => {
+                let (Some(proposal), Some(bump)) = (self.proposal.as_mut(), proposal_bump) else {
+                    return err!(TitaErrors::ProposalAccountRequired);
+                };
+
+                let governance = self.flow.governance;
+
+                open_proposal(
+                    &mut self.flow,
+                    proposal,
+                    self.creator.key(),
+                    ProposalType::MilestoneCompletion { milestone_id },
+                    governance,
+                    bump,
+                )?;
+
+                (Some(proposal.key()), Some(proposal.voting_ends_at), None)
+            },
+            ApprovalMode::Optimistic { challenge_period } => {
+                // Resubmitting restarts the window
+                let challenge_ends_at = current_time
+                    .checked_add(challenge_period)
+                    .ok_or(TitaErrors::MathOverflow)?;
+                self.flow.milestone_mut(milestone_id)?.challenge_ends_at = Some(challenge_ends_at);
+
+                (None, None, Some(challenge_ends_at))
+            },
+        };
+
+        emit!(MilestoneSubmittedEvent {
+            flow: self.flow.key(),
+            milestone_id,
+            evidence_uri,
+            evidence_hash,
+            proposal,
+            voting_ends_at,
+            challenge_ends_at,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+}
+
+// Event emitted when the creator submits a milestone for approval
+#[event]
+pub struct MilestoneSubmittedEvent {
+    pub flow: Pubkey,
+    pub milestone_id: u32,
+    pub evidence_uri: String,
+    pub evidence_hash: [u8; 32],
+    pub proposal: Option<Pubkey>,
+    pub voting_ends_at: Option<i64>,
+    pub challenge_ends_at: Option<i64>,
+    pub timestamp: i64,
+}
@@ -0,0 +1,194 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked};
+
+use crate::{
+    constants::TITA_VAULT_SEED,
+    errors::TitaErrors,
+    states::{Flow, Vault},
+};
+
+/// Brings a milestone vault's tokens in line with the flow's accounting
+///
+/// Releases, expiries and cancellations sync the vaults they free tokens from
+/// as they happen, see `release_vaults`. This covers the other direction, it
+/// tops a vault up from flow_ta when an adjustment or an added milestone locked
+/// more for its milestone. Anyone can crank it.
+#[derive(Accounts)]
+pub struct SyncVault<'info> {
+    pub cranker: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = flow.token_mint == token_mint.key() @ TitaErrors::InvalidTokenMint,
+    )]
+    pub flow: Account<'info, Flow>,
+
+    #[account(
+        mut,
+        seeds = [
+            TITA_VAULT_SEED,
+            flow.key().as_ref(),
+            &vault.milestone_id.to_le_bytes()
+        ],
+        bump = vault.bump,
+        constraint = vault.flow == flow.key() @ TitaErrors::InvalidVaultAccounts,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == vault.token_account @ TitaErrors::InvalidVaultAccounts,
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = flow_token_account.key() == flow.flow_ta @ TitaErrors::InvalidFlowTokenAccount,
+    )]
+    pub flow_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> SyncVault<'info> {
+    pub fn sync_vault(&mut self) -> Result<()> {
+        sync_vault_tokens(
+            &mut self.flow,
+            &mut self.vault,
+            self.vault_token_account.to_account_info(),
+            self.flow_token_account.to_account_info(),
+            &self.token_mint,
+            self.token_program.to_account_info(),
+        )
+    }
+}
+
+// Syncs the vaults passed as (vault, vault token account) remaining accounts,
+// moving whatever an instruction released from them to flow_ta. Every vault
+// that was released from has to be passed, check_balances fails otherwise.
+pub fn release_vaults<'info>(
+    flow: &mut Account<'info, Flow>,
+    vault_accounts: &'info [AccountInfo<'info>],
+    flow_token_account: Option<&InterfaceAccount<'info, TokenAccount>>,
+    token_mint: Option<&InterfaceAccount<'info, Mint>>,
+    token_program: Option<&Interface<'info, TokenInterface>>,
+) -> Result<()> {
+    if !flow.milestone_vaults || vault_accounts.is_empty() {
+        return Ok(());
+    }
+
+    let (Some(flow_token_account), Some(token_mint), Some(token_program)) =
+        (flow_token_account, token_mint, token_program)
+    else {
+        return err!(TitaErrors::InvalidVaultAccounts);
+    };
+
+    let pairs = vault_accounts.chunks_exact(2);
+    require!(pairs.remainder().is_empty(), TitaErrors::InvalidVaultAccounts);
+
+    for accounts in pairs {
+        let mut vault = Account::<Vault>::try_from(&accounts[0])?;
+        let vault_token_account = &accounts[1];
+
+        require!(
+            vault.flow == flow.key()
+                && vault.token_account == vault_token_account.key()
+                && accounts[0].is_writable
+                && vault_token_account.is_writable,
+            TitaErrors::InvalidVaultAccounts
+        );
+
+        sync_vault_tokens(
+            flow,
+            &mut vault,
+            vault_token_account.clone(),
+            flow_token_account.to_account_info(),
+            token_mint,
+            token_program.to_account_info(),
+        )?;
+        vault.exit(&crate::ID)?;
+    }
+
+    Ok(())
+}
+
+// Moves tokens between a vault and flow_ta until the vault holds what the
+// flow has locked for its milestone
+fn sync_vault_tokens<'info>(
+    flow: &mut Account<'info, Flow>,
+    vault: &mut Account<'info, Vault>,
+    vault_token_account: AccountInfo<'info>,
+    flow_token_account: AccountInfo<'info>,
+    token_mint: &InterfaceAccount<'info, Mint>,
+    token_program: AccountInfo<'info>,
+) -> Result<()> {
+    let milestone_id = vault.milestone_id;
+    let previous_amount = vault.amount;
+    let target = flow.vault_target(milestone_id);
+
+    let flow_seeds = flow.signer_seeds();
+    let seeds: &[&[u8]] = &flow_seeds;
+
+    let signer_seeds = &[seeds];
+
+    // The flow PDA owns both token accounts, so tokens can go either way
+    let (from, to, amount) = if previous_amount > target {
+        (vault_token_account, flow_token_account, previous_amount - target)
+    } else {
+        (flow_token_account, vault_token_account, target - previous_amount)
+    };
+
+    if amount > 0 {
+        transfer_checked(
+            CpiContext::new_with_signer(
+                token_program,
+                TransferChecked {
+                    from,
+                    mint: token_mint.to_account_info(),
+                    to,
+                    authority: flow.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            amount,
+            token_mint.decimals,
+        )?;
+    }
+
+    flow.vault_holdings = flow
+        .vault_holdings
+        .checked_sub(previous_amount)
+        .and_then(|holdings| holdings.checked_add(target))
+        .ok_or(TitaErrors::MathOverflow)?;
+
+    let milestone = flow
+        .milestones
+        .iter()
+        .flatten()
+        .find(|milestone| milestone.id == milestone_id);
+
+    vault.amount = target;
+    vault.milestone_deadline = milestone.map(|milestone| milestone.deadline);
+    vault.milestone_completed = milestone.map(|milestone| milestone.is_completed());
+
+    emit!(VaultSyncedEvent {
+        flow: vault.flow,
+        milestone_id,
+        previous_amount,
+        amount: target,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Event emitted when a milestone vault's tokens are brought in line
+#[event]
+pub struct VaultSyncedEvent {
+    pub flow: Pubkey,
+    pub milestone_id: u32,
+    pub previous_amount: u64,
+    pub amount: u64,
+    pub timestamp: i64,
+}
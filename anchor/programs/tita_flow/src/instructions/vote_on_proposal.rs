@@ -1,6 +1,7 @@
 use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 
-use crate::{errors::TitaErrors, states::{Contribution, Flow, FlowStatus, Proposal, ProposalStatus, ProposalType, VoteType, VotingPowerModel}};
+use crate::{constants::TITA_VOTE_SEED, errors::TitaErrors, instructions::release_vaults, states::{Contribution, Flow, FlowKind, FlowStatus, Proposal, ProposalStatus, ProposalType, Vote, VoteType, VotingPowerModel}};
 
 #[derive(Accounts)]
 pub struct VoteOnProposal<'info> {
@@ -16,7 +17,7 @@ pub struct VoteOnProposal<'info> {
     #[account(
         mut, 
         constraint = flow.key() == proposal.flow @ TitaErrors::InvalidFlow,
-        constraint = flow.active_proposal.unwrap() == proposal.key() @ TitaErrors::InvalidProposal
+        constraint = flow.active_proposal == Some(proposal.key()) @ TitaErrors::InvalidProposal
     )]
     pub flow: Account<'info, Flow>,
     
@@ -25,191 +26,302 @@ pub struct VoteOnProposal<'info> {
         constraint = contribution.contributor == voter.key() @ TitaErrors::UnauthorizedVoter,
     )]
     pub contribution: Account<'info, Contribution>,
-    
+
+    // One vote record per voter, kept so the vote can be changed while voting is open
+    #[account(
+        init_if_needed,
+        payer = voter,
+        space = 8 + Vote::INIT_SPACE,
+        seeds = [
+            TITA_VOTE_SEED,
+            proposal.key().as_ref(),
+            voter.key().as_ref()
+        ],
+        bump
+    )]
+    pub vote: Account<'info, Vote>,
+
+    // Flows with milestone vaults pass flow_ta, the mint and the token program,
+    // with the vaults a passing vote releases from as remaining accounts
+    #[account(
+        mut,
+        constraint = flow_token_account.key() == flow.flow_ta @ TitaErrors::InvalidFlowTokenAccount,
+    )]
+    pub flow_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        constraint = token_mint.key() == flow.token_mint @ TitaErrors::InvalidTokenMint,
+    )]
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+
     pub system_program: Program<'info, System>,
 }
 
 impl<'info> VoteOnProposal<'info> {
     pub fn vote(
         &mut self,
-        vote_type: VoteType
+        vote_type: VoteType,
+        vote_bump: u8,
+        vault_accounts: &'info [AccountInfo<'info>],
     ) -> Result<()> {
         let proposal = &mut self.proposal;
         let flow = &mut self.flow;
         let contribution = &self.contribution;
+        let vote = &mut self.vote;
+
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(current_time >= proposal.voting_starts_at, TitaErrors::VotingNotStarted);
+        require!(current_time <= proposal.voting_ends_at, TitaErrors::VotingEnded);
+
+        // Grants are governed by their donor alone
+        if let Some(donor) = flow.donor() {
+            require_keys_eq!(self.voter.key(), donor, TitaErrors::UnauthorizedDonor);
+        }
         
-        // Calculate voting power
-        let voting_power = calculate_voting_power(contribution, flow)?;
+        // Calculate voting power from what was contributed before the proposal's snapshot
+        let snapshot_amount = contribution.amount_at_snapshot(&proposal.key());
+        let voting_power = calculate_voting_power(snapshot_amount, flow.voting_power_model)?;
         
-        // Record the vote
-        match vote_type {
-            VoteType::For => {
-                proposal.votes_for = proposal.votes_for
-                    .checked_add(voting_power)
-                    .ok_or(TitaErrors::MathOverflow)?;
-            },
-            VoteType::Against => {
-                proposal.votes_against = proposal.votes_against
-                    .checked_add(voting_power)
-                    .ok_or(TitaErrors::MathOverflow)?;
-            },
-            VoteType::Abstain => {
-                proposal.votes_abstain = proposal.votes_abstain
-                    .checked_add(voting_power)
-                    .ok_or(TitaErrors::MathOverflow)?;
-            },
-        }
+        // A fresh vote account has no voter yet, otherwise the voter is changing their choice
+        let is_new_vote = vote.voter == Pubkey::default();
+        let previous_vote = (!is_new_vote).then_some((vote.vote_type, vote.voting_power));
 
-        // Check if thresholds are met for execution
-        if proposal.check_execution_threshold()? {
-            // Execute the proposal action
-            match proposal.proposal_type {
-                ProposalType::MilestoneCompletion { milestone_id } => {
-                    // First, extract the milestone amount to avoid multiple mutable borrows
-                    let milestone_amount = {
-                        // Find the milestone and get its amount
-                        if let Some(milestones) = &flow.milestones {
-                            let milestone = milestones.iter()
-                                .find(|m| m.id == milestone_id)
-                                .ok_or(TitaErrors::MilestoneNotFound)?;
-                            
-                            milestone.amount
-                        } else {
-                            return err!(TitaErrors::NotMilestoneFlow);
-                        }
-                    };
-
-                    // Now update the flow's balance using the extracted amount
-                    flow.balance = flow.balance
-                        .checked_sub(milestone_amount)
-                        .ok_or(TitaErrors::MathOverflow)?;
-
-                    // Add to flow available field
-                    flow.available = flow.available
-                        .checked_add(milestone_amount)
-                        .ok_or(TitaErrors::MathOverflow)?;
-
-                    // Now mark the milestone as completed
-                    if let Some(milestones) = &mut flow.milestones {
-                        let milestone = milestones.iter_mut()
-                            .find(|m| m.id == milestone_id)
-                            .ok_or(TitaErrors::MilestoneNotFound)?;
-                        
-                        milestone.completed = true;
-                    }
-
-                    msg!("Milestone {} has been completed by governance vote", milestone_id);
-                },
-                ProposalType::FlowCancellation => {
-                    // Ensure flow is in a state that can be canceled
-                    require!(
-                        flow.flow_status == FlowStatus::Active,
-                        TitaErrors::CannotCancelFlow
-                    );
-                    
-                    // Set flow status to canceled
-                    flow.flow_status = FlowStatus::Canceled;
-                    
-                    msg!("Flow has been canceled by governance vote");
-                    
-                    // Funds distribution after cancellation would typically be handled
-                    // by a separate instruction, as it requires different accounts
-                },
-                ProposalType::MilestoneAdjustment { milestone_id, new_amount, new_deadline } => {
-                    // Find milestone
-                    if let Some(milestones) = &mut flow.milestones {
-                        let milestnes =  &mut milestones.clone();
-                        let milestone = milestnes.iter_mut()
-                            .find(|m| m.id == milestone_id)
-                            .ok_or(TitaErrors::MilestoneNotFound)?;
-                        
-                        // Ensure milestone isn't already completed
-                        require!(!milestone.completed, TitaErrors::MilestoneAlreadyCompleted);
-                        
-                        // Update milestone amount if provided
-                        if let Some(amount) = new_amount {
-                            // Validate the new amount doesn't exceed flow goal
-                            // let current_total: u64 = milestones.iter()
-                            //     .map(|m| m.amount)
-                            //     .sum();
-                            // let adjustment = amount.checked_sub(milestone.amount)
-                            //     .ok_or(TitaErrors::MathOverflow)?;
-                            
-                            // let new_total = current_total.checked_add(adjustment)
-                            //     .ok_or(TitaErrors::MathOverflow)?;
-                            
-                            // require!(
-                            //     new_total <= flow.goal,
-                            //     TitaErrors::InvalidMilestoneAdjustment
-                            // );
-                            
-                            // Apply the new amount
-                            milestone.amount = amount;
-                            msg!("Milestone {} amount updated to {}", milestone_id, amount);
-                        }
-                        
-                        // Update milestone deadline if provided
-                        if let Some(deadline) = new_deadline {
-                            // Ensure new deadline is in the future
-                            let current_time = Clock::get()?.unix_timestamp;
-                            require!(
-                                deadline > current_time,
-                                TitaErrors::InvalidMilestoneDeadline
-                            );
-                            
-                            // Check that new deadline is before flow end date if set
-                            if let Some(end_date) = flow.end_date {
-                                require!(
-                                    deadline < end_date,
-                                    TitaErrors::InvalidMilestoneDeadline
-                                );
-                            }
-                            
-                            // Apply the new deadline
-                            milestone.deadline = deadline;
-                            msg!("Milestone {} deadline updated to {}", milestone_id, deadline);
-                        }
-                    } else {
-                        return err!(TitaErrors::NotMilestoneFlow);
-                    }
+        // Record the vote, replacing the previous choice in the tallies
+        proposal.record_vote(previous_vote, vote_type, voting_power, current_time)?;
+
+        vote.proposal = proposal.key();
+        vote.voter = self.voter.key();
+        vote.vote_type = vote_type;
+        vote.voting_power = voting_power;
+        vote.voted_at = current_time;
+        vote.bump = vote_bump;
+
+        emit!(VoteCastEvent {
+            proposal: proposal.key(),
+            voter: vote.voter,
+            vote_type,
+            voting_power,
+            changed: !is_new_vote,
+            timestamp: current_time,
+        });
+
+        // Check if thresholds are met for execution. The donor's vote is final on grants.
+        let should_execute = match flow.kind {
+            FlowKind::Raise => proposal.check_execution_threshold()?,
+            FlowKind::Distribute { .. } => match vote_type {
+                VoteType::For => {
+                    proposal.status = ProposalStatus::Passed;
+                    true
                 },
-                ProposalType::FlowFundingExtension { new_end_date } => {
-                    // Validate extension
-                    let current_time = Clock::get()?.unix_timestamp;
-                    
-                    // Ensure new end date is in the future
-                    require!(
-                        new_end_date > current_time,
-                        TitaErrors::InvalidFlowExtension
-                    );
-                    
-                    // If current end date exists, ensure new date is later
-                    if let Some(current_end_date) = flow.end_date {
-                        require!(
-                            new_end_date > current_end_date,
-                            TitaErrors::InvalidFlowExtension
-                        );
-                    }
-                    
-                    // Update the flow end date
-                    flow.end_date = Some(new_end_date);
-                    
-                    msg!("Flow end date extended to {}", new_end_date);
+                VoteType::Against => {
+                    proposal.status = ProposalStatus::Failed;
+                    flow.close_active_proposal(proposal.created_at, current_time);
+                    false
                 },
-            }
-            
-            // Update proposal status
-            proposal.status = ProposalStatus::Executed;
-            proposal.executed_at = Some(Clock::get()?.unix_timestamp);
-            
-            // Clear active proposal from flow
-            flow.active_proposal = None;
+                VoteType::Abstain => false,
+            },
+        };
+
+        if should_execute {
+            execute_or_fail_proposal(proposal, flow, current_time);
         }
+
+        release_vaults(
+            flow,
+            vault_accounts,
+            self.flow_token_account.as_ref(),
+            self.token_mint.as_ref(),
+            self.token_program.as_ref(),
+        )?;
+
+        flow.check_balances()?;
         
         Ok(())
     }
 }
 
+// Event emitted when a vote is cast or changed
+#[event]
+pub struct VoteCastEvent {
+    pub proposal: Pubkey,
+    pub voter: Pubkey,
+    pub vote_type: VoteType,
+    pub voting_power: u64,
+    pub changed: bool,
+    pub timestamp: i64,
+}
+
+
+/// Executes a passed proposal against a copy of the flow so a failing action
+/// can't leave the flow half updated or revert the vote that passed it. A
+/// proposal that can't be executed fails and frees the flow.
+pub fn execute_or_fail_proposal(
+    proposal: &mut Account<Proposal>,
+    flow: &mut Account<Flow>,
+    current_time: i64,
+) {
+    let mut updated_flow = flow.clone().into_inner();
+
+    match execute_proposal(proposal, &mut updated_flow) {
+        Ok(()) => flow.set_inner(updated_flow),
+        Err(error) => {
+            msg!("Proposal could not be executed: {:?}", error);
+            proposal.status = ProposalStatus::Failed;
+            flow.close_active_proposal(proposal.created_at, current_time);
+        }
+    }
+}
+
+/// Applies a passed proposal to its flow, marks it executed and frees the flow
+/// for the next proposal
+pub fn execute_proposal(
+    proposal: &mut Proposal,
+    flow: &mut Flow
+) -> Result<()> {
+    execute_proposal_action(proposal.proposal_type, flow)?;
+
+    // Update proposal status
+    let current_time = Clock::get()?.unix_timestamp;
+    proposal.status = ProposalStatus::Executed;
+    proposal.executed_at = Some(current_time);
+
+    // Clear active proposal from flow
+    flow.close_active_proposal(proposal.created_at, current_time);
+
+    Ok(())
+}
+
+/// Performs the action a proposal describes on the flow
+pub fn execute_proposal_action(
+    proposal_type: ProposalType,
+    flow: &mut Flow
+) -> Result<()> {
+    match proposal_type {
+        ProposalType::MilestoneCompletion { milestone_id } => {
+            // Release the milestone's share of the locked balance,
+            // straight into available or as a stream
+            let released = flow.complete_milestone(milestone_id, Clock::get()?.unix_timestamp)?;
+
+            msg!("Milestone {} has been completed by governance vote, {} released", milestone_id, released);
+        },
+        ProposalType::FlowCancellation => {
+            // Ensure flow is in a state that can be canceled
+            require!(
+                flow.flow_status == FlowStatus::Active,
+                TitaErrors::CannotCancelFlow
+            );
+            
+            // Settle streams up to now, whatever hasn't vested stays refundable
+            flow.vest_streams(Clock::get()?.unix_timestamp)?;
+
+            // Set flow status to canceled
+            flow.flow_status = FlowStatus::Canceled;
+            
+            msg!("Flow has been canceled by governance vote");
+            
+            // Funds distribution after cancellation would typically be handled
+            // by a separate instruction, as it requires different accounts
+        },
+        ProposalType::MilestoneAdjustment { milestone_id, new_amount, new_deadline } => {
+            require!(flow.flow_status != FlowStatus::Canceled, TitaErrors::FlowCanceled);
+
+            let milestone = flow
+                .milestones
+                .as_ref()
+                .ok_or(TitaErrors::NotMilestoneFlow)?
+                .iter()
+                .find(|milestone| milestone.id == milestone_id)
+                .ok_or(TitaErrors::MilestoneNotFound)?;
+            let (old_amount, old_deadline) = (milestone.amount, milestone.deadline);
+
+            flow.adjust_milestone(milestone_id, new_amount, new_deadline, Clock::get()?.unix_timestamp)?;
+
+            emit!(MilestoneAdjustedEvent {
+                flow_id: flow.flow_id.clone(),
+                creator: flow.creator,
+                milestone_id,
+                old_amount,
+                new_amount: new_amount.unwrap_or(old_amount),
+                old_deadline,
+                new_deadline: new_deadline.unwrap_or(old_deadline),
+                balance: flow.balance,
+                available: flow.available,
+            });
+
+            msg!("Milestone {} adjusted by governance vote", milestone_id);
+        },
+        ProposalType::FlowFundingExtension { new_end_date } => {
+            // Validate extension
+            let current_time = Clock::get()?.unix_timestamp;
+            
+            // Ensure new end date is in the future
+            require!(
+                new_end_date > current_time,
+                TitaErrors::InvalidFlowExtension
+            );
+            
+            // If current end date exists, ensure new date is later
+            if let Some(current_end_date) = flow.end_date {
+                require!(
+                    new_end_date > current_end_date,
+                    TitaErrors::InvalidFlowExtension
+                );
+            }
+            
+            // Update the flow end date
+            flow.end_date = Some(new_end_date);
+            
+            msg!("Flow end date extended to {}", new_end_date);
+        },
+        ProposalType::ChangeBeneficiary { new_beneficiary } => {
+            require!(
+                new_beneficiary != Pubkey::default(),
+                TitaErrors::InvalidBeneficiary
+            );
+
+            // A grant's donor can't pay themselves
+            if let Some(donor) = flow.donor() {
+                require!(new_beneficiary != donor, TitaErrors::InvalidGrantParticipants);
+            }
+
+            flow.beneficiary = new_beneficiary;
+
+            msg!("Flow beneficiary changed to {}", new_beneficiary);
+        },
+        ProposalType::PartialMilestoneRelease { milestone_id, basis_points } => {
+            // Release part of the milestone, it completes once fully released
+            let released = flow.release_milestone_portion(
+                milestone_id,
+                basis_points,
+                Clock::get()?.unix_timestamp
+            )?;
+
+            msg!(
+                "{} basis points of milestone {} released by governance vote, {} released",
+                basis_points,
+                milestone_id,
+                released
+            );
+        },
+        ProposalType::AddMilestone { amount, deadline } => {
+            require!(flow.flow_status != FlowStatus::Canceled, TitaErrors::FlowCanceled);
+
+            let milestone_id = flow.add_milestone(amount, deadline, Clock::get()?.unix_timestamp)?;
+
+            msg!("Milestone {} added with amount {} due {}", milestone_id, amount, deadline);
+        },
+        ProposalType::RemoveMilestone { milestone_id } => {
+            require!(flow.flow_status != FlowStatus::Canceled, TitaErrors::FlowCanceled);
+
+            let unlocked = flow.remove_milestone(milestone_id)?;
+
+            msg!("Milestone {} removed, {} unlocked", milestone_id, unlocked);
+        },
+    }
+
+    Ok(())
+}
 
 /// Calculate voting power based on the model selected for the flow
 ///
@@ -218,37 +330,16 @@ impl<'info> VoteOnProposal<'info> {
 /// - QuadraticVoting: Square root of token amount (reduces whale influence)
 /// - IndividualVoting: 1 contributor = 1 vote (equal voting power for all)
 pub fn calculate_voting_power(
-    contribution: &Contribution,
-    flow: &Flow
+    amount: u64,
+    voting_power_model: VotingPowerModel
 ) -> Result<u64> {
     // Ensure non-zero contribution
-    if contribution.total_amount == 0 {
+    if amount == 0 {
         return err!(TitaErrors::ZeroVotingPower);
     }
     
     // Calculate voting power based on selected model
-    match flow.voting_power_model {
-        VotingPowerModel::TokenWeighted => {
-            // Simple 1 token = 1 vote
-            // This gives influence proportional to financial contribution
-            Ok(contribution.total_amount)
-        },
-        
-        VotingPowerModel::QuadraticVoting => {
-            // Square root of contribution amount
-            // This reduces the power gap between small and large contributors
-            // Example: 100 tokens = 10 votes, 10,000 tokens = 100 votes (10x tokens = √10x votes)
-            let amount = contribution.total_amount as f64;
-            let voting_power = (amount.sqrt() as u64).max(1);
-            Ok(voting_power)
-        },
-        
-        VotingPowerModel::IndividualVoting => {
-            // Each contributor gets exactly 1 vote regardless of contribution amount
-            // This creates true democratic voting (one person, one vote)
-            Ok(1)
-        }
-    }
+    Ok(voting_power_model.weight(amount))
 }
 
 
@@ -284,4 +375,18 @@ ProposalType::VotingModelChange { new_voting_model } => {
     flow.voting_power_model = new_voting_model;
     msg!("Voting model changed");
 },
-*/
\ No newline at end of file
+*/
+
+// Event emitted when a milestone's amount or deadline is changed by governance
+#[event]
+pub struct MilestoneAdjustedEvent {
+    pub flow_id: String,
+    pub creator: Pubkey,
+    pub milestone_id: u32,
+    pub old_amount: u64,
+    pub new_amount: u64,
+    pub old_deadline: i64,
+    pub new_deadline: i64,
+    pub balance: u64,
+    pub available: u64,
+}
@@ -1,7 +1,6 @@
 use anchor_lang::prelude::*;
 use anchor_spl::token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface};
 
-use crate::constants::{TITA_FLOW_SEED};
 use crate::errors::TitaErrors;
 use crate::states::{Flow, FlowStatus};
 
@@ -16,12 +15,14 @@ pub struct Withdraw<'info> {
     #[account(
         mut,
         constraint = flow.creator == creator.key() @ TitaErrors::UnauthorizedWithdrawal,
-        constraint = flow.flow_status != FlowStatus::Canceled @ TitaErrors::FlowCanceled
+        constraint = flow.flow_status != FlowStatus::Canceled @ TitaErrors::FlowCanceled,
+        constraint = flow.splits.is_none() @ TitaErrors::PayoutSplitsConfigured
     )]
     pub flow: Account<'info, Flow>,
 
     #[account(
         mut,
+        address = flow.flow_ta @ TitaErrors::InvalidFlowTokenAccount,
         constraint = flow_token_account.mint == token_mint.key() @ TitaErrors::InvalidTokenAccount,
     )]
     pub flow_token_account: InterfaceAccount<'info, TokenAccount>,
@@ -29,6 +30,7 @@ pub struct Withdraw<'info> {
     #[account(
         mut,
         constraint = recipient_token_account.mint == flow.token_mint @ TitaErrors::InvalidTokenMint,
+        constraint = recipient_token_account.owner == flow.beneficiary @ TitaErrors::InvalidBeneficiary,
     )]
     pub recipient_token_account: InterfaceAccount<'info, TokenAccount>,
 
@@ -42,32 +44,17 @@ impl<'info> Withdraw<'info> {
         // Validate the withdrawal amount
         require!(amount > 0, TitaErrors::InvalidContributionAmount);
 
+        // Bring streamed milestone releases up to date
+        self.flow.vest_streams(Clock::get()?.unix_timestamp)?;
+
         // Check if flow has available funds for withdrawal
         if self.flow.available < amount {
             return err!(TitaErrors::InsufficientFunds);
         }
 
-        // Try to derive the PDA manually to verify
-        let (_derived_pda, derived_bump) = Pubkey::find_program_address(
-            &[
-                TITA_FLOW_SEED,
-                self.flow.flow_id.as_bytes(),
-                self.flow.creator.as_ref(),
-            ],
-            &crate::ID,
-        );
-
-        // Transfer tokens from flow treasury to recipient
-        let flow_id_bytes = self.flow.flow_id.as_bytes();
-        let creator_bytes = self.flow.creator.as_ref();
-        let bump_bytes = [derived_bump];
-
-        let seeds: &[&[u8]] = &[
-            TITA_FLOW_SEED,
-            flow_id_bytes,
-            creator_bytes,
-            &bump_bytes,
-        ];
+        // Transfer tokens from flow treasury to recipient, signed by the flow PDA
+        let flow_seeds = self.flow.signer_seeds();
+        let seeds: &[&[u8]] = &flow_seeds;
 
         let signer_seeds = &[seeds];
 
@@ -107,6 +94,8 @@ impl<'info> Withdraw<'info> {
             .checked_add(amount)
             .ok_or(TitaErrors::MathOverflow)?;
 
+        self.flow.check_balances()?;
+
         // Log withdrawal details
         msg!(
             "Withdrawn {} tokens from flow {}. Remaining available: {}",
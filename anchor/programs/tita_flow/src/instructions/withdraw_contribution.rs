@@ -1,24 +1,21 @@
 use anchor_lang::prelude::*;
 use anchor_spl::token_interface::{TokenAccount, Mint, TokenInterface, transfer_checked};
 
-use crate::constants::TITA_FLOW_TA_SEED;
 use crate::states::{Flow, FlowStatus, Contribution};
 use crate::errors::TitaErrors;
 
 /// Allows a contributor to withdraw their funds from a cancelled flow
 /// 
-/// This instruction can only be executed when a flow is in Cancelled status.
-/// The contributor will receive a proportional refund based on their contribution
-/// and the remaining funds in the flow.
+/// On a Cancelled flow the contributor will receive a proportional refund based
+/// on their contribution and the funds that were never withdrawn, locked or
+/// available. Refunds from expired milestones can be claimed at any time,
+/// even while the flow stays Active.
 #[derive(Accounts)]
 pub struct WithdrawContribution<'info> {
     #[account(mut)]
     pub contributor: Signer<'info>,
     
-    #[account(
-        mut,
-        constraint = flow.flow_status == FlowStatus::Canceled @ TitaErrors::FlowNotCanceled,
-    )]
+    #[account(mut)]
     pub flow: Account<'info, Flow>,
     
     #[account(
@@ -50,23 +47,26 @@ pub struct WithdrawContribution<'info> {
 impl<'info> WithdrawContribution<'info>{
 
     pub fn withdraw_contribution(&mut self) -> Result<()> {
-        let flow = self.flow.clone();
+        let canceled = self.flow.flow_status == FlowStatus::Canceled;
+
+        // Whatever expired milestones have handed back so far
+        self.contribution.sync_expired_refunds(self.flow.refund_per_token)?;
+        let expired_refund = self.contribution.expired_refund_owed;
+
+        // Active flows only pay out expired milestone refunds
+        require!(canceled || expired_refund > 0, TitaErrors::FlowNotCanceled);
+
         let contribution = self.contribution.clone();
         
-        // Calculate the refundable proportion
-        let total_refundable = flow.balance;
-        let total_contributions = flow.raised;
-        
-        // Calculate refund amount (proportional to contribution)
-        let refund_amount = if total_contributions == 0 {
-            0 // Avoid division by zero
+        // Share of everything not yet withdrawn, proportional to the contribution
+        let cancellation_refund = if canceled {
+            self.flow.refund_share(contribution.total_amount)?
         } else {
-            ((contribution.total_amount as u128)
-                .checked_mul(total_refundable as u128)
-                .ok_or(TitaErrors::MathOverflow)?
-                .checked_div(total_contributions as u128)
-                .ok_or(TitaErrors::MathOverflow)?) as u64
+            0
         };
+        let refund_amount = expired_refund
+            .checked_add(cancellation_refund)
+            .ok_or(TitaErrors::MathOverflow)?;
         
         // Ensure refund amount is non-zero and doesn't exceed treasury balance
         require!(refund_amount > 0, TitaErrors::RefundTooSmall);
@@ -75,17 +75,11 @@ impl<'info> WithdrawContribution<'info>{
         let treasury_balance = self.flow_token_account.amount;
         require!(refund_amount <= treasury_balance, TitaErrors::InsufficientTreasuryFunds);
         
-        // Transfer tokens from flow treasury to contributor
-        let flow = self.flow.clone();
-        let flow_key = flow.key();
-        let token_mint_key = self.token_mint.key();
-        let seeds = [
-            TITA_FLOW_TA_SEED,
-            flow_key.as_ref(),
-            token_mint_key.as_ref(),
-            &[flow.bump],
-        ];
-        let signer = &[&seeds[..]];
+        // The treasury is owned by the flow PDA, so sign with the flow's seeds
+        let flow_seeds = self.flow.signer_seeds();
+        let seeds: &[&[u8]] = &flow_seeds;
+
+        let signer = &[seeds];
 
         // Transfer tokens
         transfer_checked(
@@ -106,26 +100,37 @@ impl<'info> WithdrawContribution<'info>{
         
         // Update contribution record
         let contribution = &mut self.contribution;
-        contribution.refunded = true;
-        contribution.refund_amount = refund_amount;
-        contribution.refunded_at = Some(Clock::get()?.unix_timestamp);
+        contribution.expired_refund_owed = 0;
+        contribution.refund_amount = contribution.refund_amount
+            .checked_add(refund_amount)
+            .ok_or(TitaErrors::MathOverflow)?;
         
         // Update flow's refunded tracking
         let flow = &mut self.flow;
-        flow.total_refunded = flow.total_refunded
-            .checked_add(refund_amount)
-            .ok_or(TitaErrors::MathOverflow)?;
-        flow.refunds_count = flow.refunds_count
-            .checked_add(1)
-            .ok_or(TitaErrors::MathOverflow)?;
+        flow.claim_expired_refund(expired_refund)?;
+
+        if canceled {
+            contribution.refunded = true;
+            contribution.refunded_at = Some(Clock::get()?.unix_timestamp);
+
+            flow.deduct_refund(cancellation_refund, contribution.total_amount)?;
+            flow.refunds_count = flow.refunds_count
+                .checked_add(1)
+                .ok_or(TitaErrors::MathOverflow)?;
+
+            // Refunded contributors no longer take part in governance
+            flow.update_voting_weight(contribution.total_amount, 0)?;
+        }
+
+        flow.check_balances()?;
         
         // Log the refund
         msg!(
-            "Refunded {} tokens to contributor {}. Contribution was {} tokens ({}% of contribution)",
+            "Refunded {} tokens to contributor {}, {} from expired milestones. Contribution was {} tokens",
             refund_amount,
             self.contributor.key(),
-            contribution.total_amount,
-            (refund_amount * 100) / contribution.total_amount
+            expired_refund,
+            contribution.total_amount
         );
         
         Ok(())
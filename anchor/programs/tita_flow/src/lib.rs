@@ -9,10 +9,11 @@ pub mod states;
 use crate::states::*;
 
 pub mod constants;
-use crate::constants::*;
 
 pub mod errors;
 
+pub mod math;
+
 #[program]
 pub mod tita_flow {
     use super::*;
@@ -20,6 +21,7 @@ pub mod tita_flow {
     // Create a RAISE and DISTRIBUTE flow
     // RAISE FLOW - for accepting contributions from different users
     // DISTRIBUTE FLOW - can only have one donor, the grant it is tied to 
+    #[allow(clippy::too_many_arguments)]
     pub fn create_flow(
         ctx: Context<CreateFlow>,
         flow_id: String,
@@ -27,26 +29,55 @@ pub mod tita_flow {
         start_time: Option<i64>,
         end_time: Option<i64>,
         voting_power_model: VotingPowerModel,
-        milestones: Option<Vec<Milestone>>
+        governance: Option<GovernanceConfig>,
+        milestones: Option<Vec<MilestoneInput>>,
+        kind: FlowKind,
+        funding_mode: FundingMode,
+        release_mode: ReleaseMode,
+        approval_mode: ApprovalMode,
+        sequential_milestones: bool,
+        milestone_vaults: bool,
+        beneficiary: Option<Pubkey>,
+        splits: Option<Vec<PayoutSplit>>,
+        caps: Option<ContributionCaps>,
     ) -> Result<()> {
-        let _ = ctx.accounts.create(
+        ctx.accounts.create(
             flow_id,
             goal,
             start_time,
             end_time,
             milestones,
             voting_power_model,
-            ctx.accounts.flow.bump
+            governance,
+            kind,
+            funding_mode,
+            release_mode,
+            approval_mode,
+            sequential_milestones,
+            milestone_vaults,
+            beneficiary,
+            splits,
+            caps,
+            ctx.bumps.flow
         )?;
 
         Ok(())
     }
 
-    pub fn contribute(
-        ctx: Context<Contribute>,
+    pub fn contribute<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Contribute<'info>>,
         amount: u64,
     ) -> Result<()> {
-        let _ = ctx.accounts.contribute(amount, ctx.bumps.contribution)?;
+        ctx.accounts.contribute(amount, ctx.bumps.contribution, ctx.remaining_accounts)?;
+        Ok(())
+    }
+
+    // Only the donor of a DISTRIBUTE flow can fund it
+    pub fn fund_grant<'info>(
+        ctx: Context<'_, '_, 'info, 'info, FundGrant<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        ctx.accounts.fund_grant(amount, ctx.bumps.contribution, ctx.remaining_accounts)?;
         Ok(())
     }
     
@@ -57,22 +88,68 @@ pub mod tita_flow {
         quorum_percentage: u16,
         approval_percentage: u16,
     ) -> Result<()> {
-        let _ = ctx.accounts.create_proposal(
+        ctx.accounts.create_proposal(
             proposal_type,
             voting_duration,
             quorum_percentage,
             approval_percentage,
-            ctx.accounts.proposal.bump
+            ctx.bumps.proposal
         )?;
 
         Ok(())
     }
 
-    pub fn vote(
-        ctx: Context<VoteOnProposal>,
+    // Creator submits proof a milestone is delivered, opening its completion vote
+    pub fn submit_milestone(
+        ctx: Context<SubmitMilestone>,
+        milestone_id: u32,
+        evidence_uri: String,
+        evidence_hash: [u8; 32],
+    ) -> Result<()> {
+        ctx.accounts.submit_milestone(milestone_id, evidence_uri, evidence_hash, ctx.bumps.proposal)?;
+        Ok(())
+    }
+
+    // Contributor disputes an optimistic milestone submission, putting it to a vote
+    pub fn dispute_milestone(
+        ctx: Context<DisputeMilestone>,
+        milestone_id: u32,
+    ) -> Result<()> {
+        ctx.accounts.dispute_milestone(milestone_id, ctx.bumps.proposal)?;
+        Ok(())
+    }
+
+    // Releases an undisputed optimistic milestone once its challenge window is over
+    pub fn release_milestone<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ReleaseMilestone<'info>>,
+        milestone_id: u32,
+    ) -> Result<()> {
+        ctx.accounts.release_milestone(milestone_id, ctx.remaining_accounts)?;
+        Ok(())
+    }
+
+    // Hands a missed milestone's locked funds back to contributors
+    pub fn expire_milestone<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExpireMilestone<'info>>,
+        milestone_id: u32,
+    ) -> Result<()> {
+        ctx.accounts.expire_milestone(milestone_id, ctx.remaining_accounts)?;
+        Ok(())
+    }
+
+    pub fn vote<'info>(
+        ctx: Context<'_, '_, 'info, 'info, VoteOnProposal<'info>>,
         vote_type: VoteType,
     ) -> Result<()> {
-        let _ = ctx.accounts.vote(vote_type)?;
+        ctx.accounts.vote(vote_type, ctx.bumps.vote, ctx.remaining_accounts)?;
+        Ok(())
+    }
+
+    // Permissionless crank that resolves a proposal after its voting period
+    pub fn finalize_proposal<'info>(
+        ctx: Context<'_, '_, 'info, 'info, FinalizeProposal<'info>>,
+    ) -> Result<()> {
+        ctx.accounts.finalize_proposal(ctx.remaining_accounts)?;
         Ok(())
     }
 
@@ -80,20 +157,64 @@ pub mod tita_flow {
         ctx: Context<Withdraw>,
         amount: u64,
     ) -> Result<()> {
-        let _ = ctx.accounts.withdraw(amount)?;
+        ctx.accounts.withdraw(amount)?;
+        Ok(())
+    }
+
+    // Pays the available funds out to every split recipient at once
+    pub fn distribute<'info>(
+        ctx: Context<'_, '_, 'info, 'info, DistributeSplits<'info>>,
+    ) -> Result<()> {
+        ctx.accounts.distribute(ctx.remaining_accounts)?;
+        Ok(())
+    }
+
+    // Opens the vault and token account holding a milestone's locked funds
+    pub fn init_milestone_vault(
+        ctx: Context<InitMilestoneVault>,
+        milestone_id: u32,
+    ) -> Result<()> {
+        ctx.accounts.init_milestone_vault(milestone_id, ctx.bumps.vault)?;
+        Ok(())
+    }
+
+    // Moves tokens between a milestone vault and flow_ta to match the flow's accounting
+    pub fn sync_vault(ctx: Context<SyncVault>) -> Result<()> {
+        ctx.accounts.sync_vault()?;
+        Ok(())
+    }
+
+    // Closes funding for an all-or-nothing flow once its end date has passed
+    pub fn settle_flow(ctx: Context<SettleFlow>) -> Result<()> {
+        ctx.accounts.settle_flow()?;
+        Ok(())
+    }
+
+    // Finalizes a finished flow, paying out what's left and reclaiming rent
+    pub fn close_flow(ctx: Context<CloseFlow>) -> Result<()> {
+        ctx.accounts.close_flow()?;
+        Ok(())
+    }
+
+    // Creator abort before any withdrawal, opens refunds or closes an empty flow
+    pub fn cancel_flow<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CancelFlow<'info>>,
+        reason: u8,
+    ) -> Result<()> {
+        ctx.accounts.cancel_flow(reason, ctx.remaining_accounts)?;
         Ok(())
     }
 
     pub fn refund(ctx: Context<WithdrawContribution>)-> Result<()>{
-        let  _ = ctx.accounts.withdraw_contribution();
+        ctx.accounts.withdraw_contribution()?;
 
         Ok(())
     }
     
-    // pub fn cancel_proposal(
-    //     ctx: Context<CancelProposal>,
-    // ) -> Result<()> {
-    //     let _ = ctx.accounts.cancel_proposal()?;
-    //     Ok(())
-    // }
+    pub fn cancel_proposal(
+        ctx: Context<CancelProposal>,
+    ) -> Result<()> {
+        ctx.accounts.cancel_proposal()?;
+        Ok(())
+    }
 }
@@ -0,0 +1,142 @@
+use crate::constants::{BASIS_POINTS_DENOMINATOR, QUADRATIC_VOTING_SCALE};
+
+/// Integer square root rounded down
+///
+/// Uses Newton's method on u128 so it is deterministic on-chain and exact for
+/// every input, unlike an f64 round trip which loses precision above 2^53.
+pub fn integer_sqrt(value: u128) -> u128 {
+    if value < 2 {
+        return value;
+    }
+
+    // Start from a power of two that is guaranteed to be >= sqrt(value)
+    let bits = 128 - value.leading_zeros();
+    let mut root = 1u128 << bits.div_ceil(2);
+
+    loop {
+        let next = (root + value / root) / 2;
+        if next >= root {
+            return root;
+        }
+        root = next;
+    }
+}
+
+/// Quadratic voting weight of a contribution
+///
+/// The amount is multiplied by `scale` before taking the root, which keeps the
+/// ratio between contributors the same while adding sqrt(scale) of precision,
+/// so small contributions of high decimal tokens don't round to the same vote.
+/// Any non-zero amount is worth at least one vote.
+pub fn quadratic_weight(amount: u64, scale: u64) -> u64 {
+    if amount == 0 {
+        return 0;
+    }
+
+    // (2^64 - 1)^2 < 2^128, so neither the product nor its root can overflow
+    let root = integer_sqrt(amount as u128 * scale as u128);
+
+    (root as u64).max(1)
+}
+
+/// Quadratic voting weight using the program's default scale
+pub fn default_quadratic_weight(amount: u64) -> u64 {
+    quadratic_weight(amount, QUADRATIC_VOTING_SCALE)
+}
+
+/// `basis_points` out of 10000 of `total`, rounded down
+pub fn basis_points_share(total: u64, basis_points: u16) -> u64 {
+    // basis_points is at most u16::MAX, so the product always fits in u128
+    (total as u128 * basis_points as u128 / BASIS_POINTS_DENOMINATOR as u128) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_sqrt_small_values() {
+        let expected = [0, 1, 1, 1, 2, 2, 2, 2, 2, 3, 3];
+        for (value, root) in expected.iter().enumerate() {
+            assert_eq!(integer_sqrt(value as u128), *root, "sqrt({})", value);
+        }
+    }
+
+    #[test]
+    fn integer_sqrt_around_perfect_squares() {
+        for root in [2u128, 10, 1_000, 65_535, 4_294_967_295, 1 << 40, u64::MAX as u128] {
+            let square = root * root;
+            assert_eq!(integer_sqrt(square), root);
+            assert_eq!(integer_sqrt(square - 1), root - 1);
+            assert_eq!(integer_sqrt(square + 1), root);
+        }
+    }
+
+    #[test]
+    fn integer_sqrt_extremes() {
+        assert_eq!(integer_sqrt(u64::MAX as u128), u32::MAX as u128);
+        assert_eq!(integer_sqrt(u128::MAX), u64::MAX as u128);
+    }
+
+    #[test]
+    fn integer_sqrt_is_exact_above_f64_precision() {
+        // (2^53 + 1)^2 is not representable as f64, the float root is off by one
+        let root = (1u128 << 53) + 1;
+        assert_eq!(integer_sqrt(root * root), root);
+        assert_eq!(integer_sqrt(root * root - 1), root - 1);
+    }
+
+    #[test]
+    fn quadratic_weight_zero_and_minimum() {
+        assert_eq!(quadratic_weight(0, QUADRATIC_VOTING_SCALE), 0);
+        assert_eq!(quadratic_weight(1, 1), 1);
+        assert_eq!(quadratic_weight(3, 1), 1);
+        assert_eq!(quadratic_weight(4, 1), 2);
+    }
+
+    #[test]
+    fn quadratic_weight_scale_separates_small_contributions() {
+        // Without scaling 1 and 3 base units collapse to the same single vote
+        assert_eq!(quadratic_weight(1, 1), quadratic_weight(3, 1));
+        assert!(quadratic_weight(1, QUADRATIC_VOTING_SCALE) < quadratic_weight(3, QUADRATIC_VOTING_SCALE));
+
+        // Scaling by a perfect square multiplies every weight by its root
+        assert_eq!(quadratic_weight(100, 1_000_000), 10 * 1_000);
+        assert_eq!(quadratic_weight(10_000, 1_000_000), 100 * 1_000);
+    }
+
+    #[test]
+    fn quadratic_weight_large_balances() {
+        assert_eq!(quadratic_weight(u64::MAX, 1), u32::MAX as u64);
+        assert_eq!(quadratic_weight(u64::MAX, u64::MAX), u64::MAX);
+
+        // One billion tokens with 9 decimals
+        let amount = 1_000_000_000u64 * 1_000_000_000;
+        assert_eq!(quadratic_weight(amount, 1), 1_000_000_000);
+        assert_eq!(
+            default_quadratic_weight(amount),
+            integer_sqrt(amount as u128 * QUADRATIC_VOTING_SCALE as u128) as u64
+        );
+    }
+
+    #[test]
+    fn quadratic_weight_is_monotonic() {
+        let mut previous = 0;
+        for amount in (0..5_000u64).chain([u64::MAX - 2, u64::MAX - 1, u64::MAX]) {
+            let weight = default_quadratic_weight(amount);
+            assert!(weight >= previous, "weight dropped at {}", amount);
+            previous = weight;
+        }
+    }
+
+    #[test]
+    fn basis_points_share_rounds_down() {
+        assert_eq!(basis_points_share(0, 5_000), 0);
+        assert_eq!(basis_points_share(10_000, 2_500), 2_500);
+        assert_eq!(basis_points_share(1, 9_999), 0);
+        assert_eq!(basis_points_share(3, 3_333), 0);
+        assert_eq!(basis_points_share(100, 3_333), 33);
+        assert_eq!(basis_points_share(u64::MAX, 10_000), u64::MAX);
+        assert_eq!(basis_points_share(u64::MAX, 5_000), u64::MAX / 2);
+    }
+}
@@ -1,5 +1,7 @@
 use anchor_lang::prelude::*;
 
+use crate::{constants::REFUND_PER_TOKEN_SCALE, errors::TitaErrors};
+
 #[account]
 #[derive(InitSpace)]
 pub struct Contribution {       
@@ -15,5 +17,195 @@ pub struct Contribution {
     pub refund_amount: u64,        // Amount to be refunded
     pub refunded_at: Option<i64>, // Timestamp of refund
     
+    pub snapshot_proposal: Option<Pubkey>, // Proposal open when this contribution last changed
+    pub snapshot_amount: u64,               // Amount held at that proposal's snapshot
+
+    pub expired_refund_owed: u64,   // Expired milestone refunds accrued but not claimed
+    pub expired_refund_debt: u64,   // Accrued refunds already accounted for at the current amount
+
     pub bump: u8,
 }
+
+impl Contribution {
+    // Remembers the amount held before the active proposal's snapshot.
+    // Must be called before the contribution amount changes.
+    pub fn checkpoint(&mut self, active_proposal: Option<Pubkey>) {
+        if active_proposal.is_some() && self.snapshot_proposal != active_proposal {
+            self.snapshot_proposal = active_proposal;
+            self.snapshot_amount = self.total_amount;
+        }
+    }
+
+    // Amount that counts towards voting on the given proposal
+    pub fn amount_at_snapshot(&self, proposal: &Pubkey) -> u64 {
+        if self.snapshot_proposal == Some(*proposal) {
+            self.snapshot_amount
+        } else {
+            self.total_amount
+        }
+    }
+
+    // Expired milestone refunds the current amount has earned at `refund_per_token`
+    fn accrued_expired_refund(&self, refund_per_token: u128) -> Result<u64> {
+        let accrued = (self.total_amount as u128)
+            .checked_mul(refund_per_token)
+            .ok_or(TitaErrors::MathOverflow)?
+            / REFUND_PER_TOKEN_SCALE;
+
+        u64::try_from(accrued).map_err(|_| error!(TitaErrors::MathOverflow))
+    }
+
+    // Moves refunds from milestones that expired since the last sync into
+    // `expired_refund_owed`. Must be called before the contribution amount changes,
+    // followed by `rebase_expired_refunds` once it has.
+    pub fn sync_expired_refunds(&mut self, refund_per_token: u128) -> Result<()> {
+        let accrued = self.accrued_expired_refund(refund_per_token)?;
+
+        self.expired_refund_owed = self
+            .expired_refund_owed
+            .checked_add(accrued.saturating_sub(self.expired_refund_debt))
+            .ok_or(TitaErrors::MathOverflow)?;
+        self.expired_refund_debt = accrued;
+
+        Ok(())
+    }
+
+    // Amounts added now don't share in milestones that expired before
+    pub fn rebase_expired_refunds(&mut self, refund_per_token: u128) -> Result<()> {
+        self.expired_refund_debt = self.accrued_expired_refund(refund_per_token)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{instructions::calculate_voting_power, states::VotingPowerModel};
+
+    fn test_contribution(total_amount: u64) -> Contribution {
+        Contribution {
+            flow: Pubkey::default(),
+            contributor: Pubkey::default(),
+            total_amount,
+            first_contribution: 0,
+            last_contribution: 0,
+            contribution_count: 1,
+            token_mint: Pubkey::default(),
+            refunded: false,
+            refund_amount: 0,
+            refunded_at: None,
+            snapshot_proposal: None,
+            snapshot_amount: 0,
+            expired_refund_owed: 0,
+            expired_refund_debt: 0,
+            bump: 255,
+        }
+    }
+
+    // Refund per contributed token of `numerator / denominator`
+    fn rate(numerator: u128, denominator: u128) -> u128 {
+        numerator * REFUND_PER_TOKEN_SCALE / denominator
+    }
+
+    #[test]
+    fn checkpoint_keeps_the_amount_from_before_the_proposal() {
+        let proposal = Pubkey::new_unique();
+        let mut contribution = test_contribution(500);
+
+        // A top-up while the vote is open doesn't count towards it
+        contribution.checkpoint(Some(proposal));
+        contribution.total_amount += 300;
+        assert_eq!(contribution.amount_at_snapshot(&proposal), 500);
+
+        // Later top-ups during the same vote keep the first snapshot
+        contribution.checkpoint(Some(proposal));
+        contribution.total_amount += 200;
+        assert_eq!(contribution.amount_at_snapshot(&proposal), 500);
+
+        // Every other proposal sees the whole amount
+        assert_eq!(contribution.amount_at_snapshot(&Pubkey::new_unique()), 1_000);
+    }
+
+    #[test]
+    fn checkpoint_moves_to_the_next_proposal() {
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut contribution = test_contribution(500);
+
+        contribution.checkpoint(Some(first));
+        contribution.total_amount += 300;
+
+        contribution.checkpoint(Some(second));
+        contribution.total_amount += 200;
+        assert_eq!(contribution.amount_at_snapshot(&second), 800);
+        assert_eq!(contribution.amount_at_snapshot(&first), 1_000);
+    }
+
+    #[test]
+    fn checkpoint_without_an_open_proposal_takes_no_snapshot() {
+        let proposal = Pubkey::new_unique();
+        let mut contribution = test_contribution(500);
+
+        contribution.checkpoint(None);
+        contribution.total_amount += 300;
+        assert_eq!(contribution.snapshot_proposal, None);
+        assert_eq!(contribution.amount_at_snapshot(&proposal), 800);
+    }
+
+    #[test]
+    fn first_contribution_during_a_vote_has_no_voting_power() {
+        let proposal = Pubkey::new_unique();
+        let mut contribution = test_contribution(0);
+
+        contribution.checkpoint(Some(proposal));
+        contribution.total_amount = 300;
+
+        let snapshot_amount = contribution.amount_at_snapshot(&proposal);
+        assert_eq!(snapshot_amount, 0);
+        assert_eq!(
+            calculate_voting_power(snapshot_amount, VotingPowerModel::TokenWeighted).unwrap_err(),
+            TitaErrors::ZeroVotingPower.into()
+        );
+    }
+
+    #[test]
+    fn sync_expired_refunds_accrues_once() {
+        let mut contribution = test_contribution(500);
+
+        contribution.sync_expired_refunds(rate(1, 5)).unwrap();
+        assert_eq!((contribution.expired_refund_owed, contribution.expired_refund_debt), (100, 100));
+
+        // Nothing new expired, nothing new owed
+        contribution.sync_expired_refunds(rate(1, 5)).unwrap();
+        assert_eq!(contribution.expired_refund_owed, 100);
+
+        contribution.sync_expired_refunds(rate(3, 10)).unwrap();
+        assert_eq!(contribution.expired_refund_owed, 150);
+    }
+
+    #[test]
+    fn sync_expired_refunds_skips_expiries_before_a_top_up() {
+        let mut contribution = test_contribution(500);
+        contribution.sync_expired_refunds(rate(1, 5)).unwrap();
+
+        contribution.total_amount = 1_000;
+        contribution.rebase_expired_refunds(rate(1, 5)).unwrap();
+        contribution.sync_expired_refunds(rate(1, 5)).unwrap();
+        assert_eq!(contribution.expired_refund_owed, 100);
+
+        // Later expiries count the whole amount
+        contribution.sync_expired_refunds(rate(3, 10)).unwrap();
+        assert_eq!(contribution.expired_refund_owed, 200);
+    }
+
+    #[test]
+    fn sync_expired_refunds_rounds_down() {
+        let mut contribution = test_contribution(3);
+
+        contribution.sync_expired_refunds(rate(1, 2)).unwrap();
+        assert_eq!(contribution.expired_refund_owed, 1);
+
+        contribution.sync_expired_refunds(rate(1, 1)).unwrap();
+        assert_eq!(contribution.expired_refund_owed, 3);
+    }
+}
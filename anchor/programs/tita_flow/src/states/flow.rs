@@ -1,10 +1,48 @@
 use anchor_lang::prelude::*;
 
+use crate::{
+    constants::{
+        BASIS_POINTS_DENOMINATOR, DEFAULT_APPROVAL_PERCENTAGE, DEFAULT_QUORUM_PERCENTAGE,
+        DEFAULT_VOTING_PERIOD, MAX_MILESTONES, REFUND_PER_TOKEN_SCALE, TITA_FLOW_SEED,
+    },
+    errors::TitaErrors,
+    math::{basis_points_share, default_quadratic_weight},
+};
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
 pub enum FlowStatus {
     Active,
     Completed,
     Canceled,
+    Pending,    // Created with a future start date, not open yet
+}
+
+// Determines who funds the flow and who approves its releases
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum FlowKind {
+    // RAISE - accepts contributions from anyone, governed by contributor votes
+    Raise,
+
+    // DISTRIBUTE - single donor grant, the donor approves releases to the beneficiary
+    Distribute {
+        donor: Pubkey,
+    },
+}
+
+// Milestone as supplied to create_flow
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct MilestoneInput {
+    pub id: u32,               // Unique identifier for the milestone
+    pub amount: u64,            // Amount allocated to this milestone
+    pub deadline: i64,          // When this milestone is due
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum MilestoneStatus {
+    Pending,    // Not delivered yet
+    Submitted,  // Creator has submitted evidence, awaiting approval
+    Completed,  // Approved, its funds have been released
+    Expired,    // Deadline missed, its funds went back to contributors
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
@@ -12,9 +50,208 @@ pub struct Milestone {
     pub id: u32,               // Unique identifier for the milestone
     pub amount: u64,            // Amount allocated to this milestone
     pub deadline: i64,          // When this milestone is due
-    pub completed: bool,        // Whether the milestone is completed
+    pub status: MilestoneStatus, // Pending, Submitted, Completed or Expired
+    #[max_len(128)]
+    pub evidence_uri: String,   // Where the creator's proof of delivery lives
+    pub evidence_hash: [u8; 32], // Hash of the evidence content
+    pub submitted_at: Option<i64>, // When evidence was last submitted
+    pub challenge_ends_at: Option<i64>, // Optimistic flows, release is possible after this unless disputed
+    pub released_amount: u64,   // Part of `amount` approved for release so far
+    pub released: u64,          // Locked funds released to this milestone so far
+    pub vested: u64,            // Part of `released` that has reached available
+    pub stream_start: i64,      // When the latest release started vesting
+    pub stream_base: u64,       // Amount already vested when the latest release started
+    pub refund_pool: u64,       // Locked funds returned to contributors when it expired
+    pub locked: u64,            // Flows with milestone vaults, locked funds held in this milestone's vault
+}
+
+impl From<MilestoneInput> for Milestone {
+    fn from(input: MilestoneInput) -> Self {
+        Milestone {
+            id: input.id,
+            amount: input.amount,
+            deadline: input.deadline,
+            status: MilestoneStatus::Pending,
+            evidence_uri: String::new(),
+            evidence_hash: [0; 32],
+            submitted_at: None,
+            challenge_ends_at: None,
+            released_amount: 0,
+            released: 0,
+            vested: 0,
+            stream_start: 0,
+            stream_base: 0,
+            refund_pool: 0,
+            locked: 0,
+        }
+    }
+}
+
+impl Milestone {
+    pub fn is_completed(&self) -> bool {
+        self.status == MilestoneStatus::Completed
+    }
+
+    // Completed or expired, its funds have left the locked balance either way
+    pub fn is_resolved(&self) -> bool {
+        matches!(self.status, MilestoneStatus::Completed | MilestoneStatus::Expired)
+    }
+
+    // Part of `amount` not released yet
+    pub fn unreleased_amount(&self) -> u64 {
+        self.amount.saturating_sub(self.released_amount)
+    }
+
+    // Part of the released funds vested by `current_time`. What is still
+    // unvested when a release is made vests linearly from then until the
+    // milestone's deadline, a release made at or after the deadline vests right away.
+    pub fn vested_at(&self, current_time: i64) -> u64 {
+        if current_time >= self.deadline || self.deadline <= self.stream_start {
+            return self.released;
+        }
+
+        let elapsed = current_time.saturating_sub(self.stream_start).max(0) as u128;
+        let duration = (self.deadline - self.stream_start) as u128;
+        let streaming = self.released.saturating_sub(self.stream_base) as u128;
+
+        // elapsed < duration, so this never exceeds `released`
+        self.stream_base + (streaming * elapsed / duration) as u64
+    }
+}
+
+// Voting parameters for proposals the program opens on the flow's behalf
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct GovernanceConfig {
+    pub voting_period: i64,         // How long voting stays open, in seconds
+    pub quorum_percentage: u16,     // Required participation (basis points)
+    pub approval_percentage: u16,   // Required approval (basis points)
+}
+
+impl Default for GovernanceConfig {
+    fn default() -> Self {
+        GovernanceConfig {
+            voting_period: DEFAULT_VOTING_PERIOD,
+            quorum_percentage: DEFAULT_QUORUM_PERCENTAGE,
+            approval_percentage: DEFAULT_APPROVAL_PERCENTAGE,
+        }
+    }
 }
 
+impl GovernanceConfig {
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.voting_period > 0
+                && self.quorum_percentage <= BASIS_POINTS_DENOMINATOR
+                && self.approval_percentage > 0
+                && self.approval_percentage <= BASIS_POINTS_DENOMINATOR,
+            TitaErrors::InvalidGovernanceConfig
+        );
+
+        Ok(())
+    }
+
+    // Proposals can ask for a longer vote or higher thresholds than the flow's
+    // own settings, never less
+    pub fn validate_against(&self, flow_governance: &GovernanceConfig) -> Result<()> {
+        require!(
+            self.voting_period >= flow_governance.voting_period
+                && self.quorum_percentage >= flow_governance.quorum_percentage
+                && self.approval_percentage >= flow_governance.approval_percentage,
+            TitaErrors::GovernanceBelowFlowSettings
+        );
+
+        Ok(())
+    }
+}
+
+// Fair launch limits on how much a flow takes in
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct ContributionCaps {
+    pub hard_cap: Option<u64>,          // Most the flow can raise, the goal when not set
+    pub min_contribution: u64,          // Least a wallet can have contributed in total
+    pub max_contribution: Option<u64>,  // Most a wallet can contribute in total
+}
+
+impl ContributionCaps {
+    pub fn validate(&self, goal: u64) -> Result<()> {
+        let hard_cap = self.hard_cap.unwrap_or(goal);
+        require!(hard_cap >= goal, TitaErrors::InvalidContributionCaps);
+
+        if let Some(max_contribution) = self.max_contribution {
+            require!(
+                max_contribution > 0 && max_contribution >= self.min_contribution,
+                TitaErrors::InvalidContributionCaps
+            );
+        }
+
+        require!(self.min_contribution <= hard_cap, TitaErrors::InvalidContributionCaps);
+
+        Ok(())
+    }
+}
+
+// Milestone ids must be unique and deadlines strictly increasing, so the
+// list order is the delivery order
+pub fn validate_milestone_schedule(schedule: impl IntoIterator<Item = (u32, i64)>) -> Result<()> {
+    let mut seen_ids: Vec<u32> = Vec::new();
+    let mut previous_deadline: Option<i64> = None;
+
+    for (id, deadline) in schedule {
+        require!(!seen_ids.contains(&id), TitaErrors::DuplicateMilestoneId);
+        seen_ids.push(id);
+
+        if let Some(previous) = previous_deadline {
+            require!(deadline > previous, TitaErrors::MilestoneDeadlinesNotIncreasing);
+        }
+        previous_deadline = Some(deadline);
+    }
+
+    Ok(())
+}
+
+// How a completed milestone's funds reach available
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum ReleaseMode {
+    // The whole release is available as soon as the milestone is completed
+    Instant,
+
+    // The release vests linearly until the milestone's deadline,
+    // a canceled flow stops vesting and the rest stays refundable
+    Streaming,
+}
+
+// How submitted milestones get approved
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum ApprovalMode {
+    // Every submission opens a completion vote that has to pass
+    Governance,
+
+    // Submissions are approved unless a contributor disputes them within
+    // `challenge_period` seconds, a dispute falls back to a regular vote
+    Optimistic {
+        challenge_period: i64,
+    },
+}
+
+// A recipient's cut of every payout from a flow with splits
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct PayoutSplit {
+    pub recipient: Pubkey,      // Wallet that owns the receiving token account
+    pub basis_points: u16,      // Share of each payout, all splits sum to 10000
+    pub distributed: u64,       // Total paid out to this recipient so far
+}
+
+
+// How contributions are released to the creator
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum FundingMode {
+    // Funds become available as they are contributed
+    Flexible,
+
+    // Funds stay locked until end_date, the flow only succeeds if the goal is met
+    // otherwise every contributor is refunded in full
+    AllOrNothing,
+}
 
 // Define available voting power models
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
@@ -29,6 +266,31 @@ pub enum VotingPowerModel {
     IndividualVoting,
 }
 
+impl VotingPowerModel {
+    // Voting weight held by a contributor who has put in `amount`
+    pub fn weight(&self, amount: u64) -> u64 {
+        if amount == 0 {
+            return 0;
+        }
+
+        match self {
+            // Simple 1 token = 1 vote
+            // This gives influence proportional to financial contribution
+            VotingPowerModel::TokenWeighted => amount,
+
+            // Square root of contribution amount
+            // This reduces the power gap between small and large contributors
+            // Example: 100 tokens = 10 votes, 10,000 tokens = 100 votes (10x tokens = √10x votes)
+            // Computed with integer math, scaled by QUADRATIC_VOTING_SCALE
+            VotingPowerModel::QuadraticVoting => default_quadratic_weight(amount),
+
+            // Each contributor gets exactly 1 vote regardless of contribution amount
+            // This creates true democratic voting (one person, one vote)
+            VotingPowerModel::IndividualVoting => 1,
+        }
+    }
+}
+
 
 #[account]
 #[derive(InitSpace)]
@@ -36,30 +298,1522 @@ pub struct Flow {
     #[max_len(32)]
     pub flow_id: String,         // Unique identifier
     pub creator: Pubkey,         // Flow creator
+    pub beneficiary: Pubkey,     // Receives withdrawals, the grantee on grant flows
     pub token_mint: Pubkey,      // Token being used
+    pub kind: FlowKind,          // Raise or Distribute
+    pub funding_mode: FundingMode, // Flexible or AllOrNothing
+    pub release_mode: ReleaseMode, // Instant or Streaming milestone releases
+    pub approval_mode: ApprovalMode, // Governance or Optimistic milestone approval
+    pub sequential_milestones: bool, // Milestones must be completed in order
+    pub milestone_vaults: bool,  // Locked funds are held in a vault per milestone instead of flow_ta
+    pub vault_holdings: u64,     // Tokens sitting in milestone vaults, flow_ta holds the rest
 
     pub goal: u64,               // Target amount
+    pub caps: Option<ContributionCaps>, // Hard cap and per wallet limits, uncapped when not set
     pub raised: u64,             // Total raised so far
 
-    pub balance: u64,          // Amount in the flow
+    // Every raised token is in exactly one of these buckets:
+    // raised == balance + available + withdrawn + refund_pool + total_refunded
+    pub balance: u64,          // Amount locked for milestones (or until an all-or-nothing flow settles)
     pub available: u64,           // Amount available for withdrawal
     pub withdrawn: u64,           // Amount withdrawn
+    pub refund_pool: u64,         // Expired milestone funds contributors haven't claimed yet
 
     pub flow_ta: Pubkey,         // Flow treasury account
     
     pub start_date: Option<i64>, // When flow funding starts
     pub end_date: Option<i64>,   // When flow funding ends
-    pub flow_status: FlowStatus, // Active/Completed/Canceled
+    pub flow_status: FlowStatus, // Pending/Active/Completed/Canceled
     pub contributor_count: u32,  // Number of contributors
     #[max_len(10)]              // Maximum 10 milestones
     pub milestones: Option<Vec<Milestone>>, // Milestone data (only used if flow_type is Milestone)
+    #[max_len(8)]               // Maximum 8 recipients
+    pub splits: Option<Vec<PayoutSplit>>, // Paid through distribute instead of withdraw when set
 
     pub proposal_count: u8,         // Unique identifier for the proposal
     pub voting_power_model: VotingPowerModel, // Selected voting power calculation model
+    pub governance: GovernanceConfig, // Used for proposals opened by the program, e.g. on milestone submission
     pub active_proposal: Option<Pubkey>,
+    pub total_voting_weight: u64,   // Sum of every contributor's weight under the voting model
 
     pub total_refunded: u64, // Total amount refunded
+    pub refunded_contributions: u64, // Sum of the contributions that have been refunded
     pub refunds_count: u32, // Number of refunds made
+    pub refund_per_token: u128, // Expired milestone refunds per contributed token, scaled by REFUND_PER_TOKEN_SCALE
 
     pub bump: u8
-}
\ No newline at end of file
+}
+
+impl Flow {
+    // Seeds the flow PDA signs its token transfers with
+    pub fn signer_seeds(&self) -> [&[u8]; 4] {
+        [
+            TITA_FLOW_SEED,
+            self.flow_id.as_bytes(),
+            self.creator.as_ref(),
+            std::slice::from_ref(&self.bump),
+        ]
+    }
+
+    pub fn donor(&self) -> Option<Pubkey> {
+        match self.kind {
+            FlowKind::Raise => None,
+            FlowKind::Distribute { donor, .. } => Some(donor),
+        }
+    }
+
+
+    pub fn milestone_mut(&mut self, milestone_id: u32) -> Result<&mut Milestone> {
+        self.milestones
+            .as_mut()
+            .ok_or(TitaErrors::NotMilestoneFlow)?
+            .iter_mut()
+            .find(|milestone| milestone.id == milestone_id)
+            .ok_or(TitaErrors::MilestoneNotFound.into())
+    }
+
+    // Latest of the end date and the milestone deadlines
+    pub fn last_deadline(&self) -> Option<i64> {
+        let milestone_deadline = self
+            .milestones
+            .as_ref()
+            .and_then(|milestones| milestones.iter().map(|milestone| milestone.deadline).max());
+
+        self.end_date.max(milestone_deadline)
+    }
+
+    // True once every milestone of a milestone flow has been completed or has expired
+    pub fn all_milestones_resolved(&self) -> bool {
+        match &self.milestones {
+            Some(milestones) => milestones.iter().all(|milestone| milestone.is_resolved()),
+            None => false,
+        }
+    }
+
+    // Pending or Active, i.e. not yet settled, completed or canceled
+    pub fn is_open(&self) -> bool {
+        matches!(self.flow_status, FlowStatus::Pending | FlowStatus::Active)
+    }
+
+    // How much of `amount` a wallet that has contributed `wallet_total` so far
+    // can put in. Anything over the hard cap is left with the contributor, going
+    // over the wallet limit or staying under its minimum fails.
+    pub fn accepted_contribution(&self, amount: u64, wallet_total: u64) -> Result<u64> {
+        let Some(caps) = self.caps else {
+            return Ok(amount);
+        };
+
+        let hard_cap = caps.hard_cap.unwrap_or(self.goal);
+        let room = hard_cap.saturating_sub(self.raised);
+        require!(room > 0, TitaErrors::HardCapReached);
+
+        let accepted = amount.min(room);
+        let new_total = wallet_total
+            .checked_add(accepted)
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        if let Some(max_contribution) = caps.max_contribution {
+            require!(new_total <= max_contribution, TitaErrors::ContributionAboveMaximum);
+        }
+
+        // The last contribution may be whatever is left under the cap
+        require!(
+            new_total >= caps.min_contribution || accepted == room,
+            TitaErrors::ContributionBelowMinimum
+        );
+
+        Ok(accepted)
+    }
+
+    // Moves a pending flow to Active once its start date has passed.
+    // Fails with FlowNotStarted if the flow is still waiting to open.
+    pub fn activate_if_started(&mut self, current_time: i64) -> Result<()> {
+        if let Some(start_date) = self.start_date {
+            require!(current_time >= start_date, TitaErrors::FlowNotStarted);
+        }
+
+        if self.flow_status == FlowStatus::Pending {
+            self.flow_status = FlowStatus::Active;
+            msg!("Flow {} is now active", self.flow_id);
+        }
+
+        Ok(())
+    }
+
+    // Frees the flow for the next proposal. Optimistic challenge windows are
+    // paused while a proposal is open, since nobody can dispute until it's
+    // resolved, so they're pushed back by however long it was open.
+    pub fn close_active_proposal(&mut self, opened_at: i64, current_time: i64) {
+        if self.active_proposal.take().is_none() {
+            return;
+        }
+
+        let paused_for = current_time.saturating_sub(opened_at).max(0);
+
+        for milestone in self.milestones.iter_mut().flatten() {
+            if let Some(challenge_ends_at) = milestone.challenge_ends_at.as_mut() {
+                if *challenge_ends_at > opened_at {
+                    *challenge_ends_at = challenge_ends_at.saturating_add(paused_for);
+                }
+            }
+        }
+    }
+
+    // Keeps the running voting weight in sync when a contributor's total changes
+    pub fn update_voting_weight(&mut self, old_amount: u64, new_amount: u64) -> Result<()> {
+        let old_weight = self.voting_power_model.weight(old_amount);
+        let new_weight = self.voting_power_model.weight(new_amount);
+
+        self.total_voting_weight = self
+            .total_voting_weight
+            .checked_sub(old_weight)
+            .and_then(|weight| weight.checked_add(new_weight))
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        Ok(())
+    }
+
+    // Part of `amount` that isn't reserved for milestones
+    pub fn unlocked_share(&self, amount: u64) -> Result<u64> {
+        // if there's no milestone all should be available for withdrawal
+        let Some(milestones) = &self.milestones else {
+            return Ok(amount);
+        };
+
+        // If there are milestones, calculate the total still to be delivered,
+        // completed or expired ones don't need funds locked for them anymore
+        let milestone_total = milestones
+            .iter()
+            .filter(|milestone| !milestone.is_resolved())
+            .try_fold(0u64, |sum, milestone| sum.checked_add(milestone.unreleased_amount()))
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        // If milestone total is less than goal, a portion of each contribution should be immediately available
+        if milestone_total >= self.goal {
+            return Ok(0);
+        }
+
+        // Calculate the proportion that should be immediately available
+        let available_ratio = self.goal - milestone_total;
+
+        let immediate_available = (amount as u128)
+            .checked_mul(available_ratio as u128)
+            .ok_or(TitaErrors::MathOverflow)?
+            / self.goal as u128;
+
+        Ok(immediate_available as u64)
+    }
+
+    // Adds a contribution to the flow totals and unlocks the part that
+    // isn't reserved for milestones. With milestone vaults, returns how much of
+    // the locked part goes to each outstanding milestone's vault, in milestone order.
+    pub fn record_contribution(&mut self, amount: u64) -> Result<Vec<u64>> {
+        let mut vault_deposits = Vec::new();
+
+        match self.funding_mode {
+            FundingMode::Flexible => {
+                let immediate_available = self.unlocked_share(amount)?;
+                let locked = amount - immediate_available;
+
+                self.available = self
+                    .available
+                    .checked_add(immediate_available)
+                    .ok_or(TitaErrors::MathOverflow)?;
+
+                // The rest stays locked until milestones are completed
+                self.balance = self
+                    .balance
+                    .checked_add(locked)
+                    .ok_or(TitaErrors::MathOverflow)?;
+
+                if self.milestone_vaults {
+                    vault_deposits = self.allocate_to_vaults(locked)?;
+                }
+            },
+            FundingMode::AllOrNothing => {
+                // Everything is held until the flow is settled
+                self.balance = self
+                    .balance
+                    .checked_add(amount)
+                    .ok_or(TitaErrors::MathOverflow)?;
+            },
+        }
+
+        self.raised = self
+            .raised
+            .checked_add(amount)
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        Ok(vault_deposits)
+    }
+
+    // Splits `locked` between the outstanding milestones by what they still
+    // have to release, the last one takes the rounding remainder
+    fn allocate_to_vaults(&mut self, locked: u64) -> Result<Vec<u64>> {
+        // Every milestone may have been removed, nothing is locked then
+        let Some(milestones) = self.milestones.as_mut() else {
+            return Ok(Vec::new());
+        };
+
+        let outstanding = milestones
+            .iter()
+            .filter(|milestone| !milestone.is_resolved())
+            .try_fold(0u64, |sum, milestone| sum.checked_add(milestone.unreleased_amount()))
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        let outstanding_count = milestones.iter().filter(|milestone| !milestone.is_resolved()).count();
+        let mut remaining = locked;
+        let mut deposits = Vec::with_capacity(outstanding_count);
+
+        for milestone in milestones.iter_mut().filter(|milestone| !milestone.is_resolved()) {
+            let deposit = if deposits.len() + 1 == outstanding_count || outstanding == 0 {
+                remaining
+            } else {
+                ((locked as u128 * milestone.unreleased_amount() as u128 / outstanding as u128) as u64)
+                    .min(remaining)
+            };
+
+            remaining -= deposit;
+            milestone.locked = milestone
+                .locked
+                .checked_add(deposit)
+                .ok_or(TitaErrors::MathOverflow)?;
+            deposits.push(deposit);
+        }
+
+        Ok(deposits)
+    }
+
+    // Locked funds backing `portion` of a milestone's amount, leaving out funds
+    // already streaming. The balance is split pro rata between what's still
+    // outstanding across the milestones, so an over or under funded flow
+    // settles everything by its last milestone.
+    fn milestone_share(&self, milestone_id: u32, portion: u64) -> Result<u64> {
+        require!(self.flow_status != FlowStatus::Canceled, TitaErrors::FlowCanceled);
+
+        // Nothing moves before an all-or-nothing flow knows it succeeded
+        require!(
+            self.funding_mode == FundingMode::Flexible || self.flow_status == FlowStatus::Completed,
+            TitaErrors::FlowNotSettled
+        );
+
+        // Funds still vesting for earlier milestones aren't up for grabs
+        let unreleased = self
+            .balance
+            .checked_sub(self.locked_in_streams()?)
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        let milestones = self.milestones.as_ref().ok_or(TitaErrors::NotMilestoneFlow)?;
+
+        let outstanding = milestones
+            .iter()
+            .filter(|milestone| !milestone.is_resolved())
+            .try_fold(0u64, |sum, milestone| sum.checked_add(milestone.unreleased_amount()))
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        let milestone = milestones
+            .iter()
+            .find(|milestone| milestone.id == milestone_id)
+            .ok_or(TitaErrors::MilestoneNotFound)?;
+
+        require!(!milestone.is_completed(), TitaErrors::MilestoneAlreadyCompleted);
+        require!(milestone.status != MilestoneStatus::Expired, TitaErrors::MilestoneExpired);
+
+        // Vaulted funds belong to their milestone, nothing is shared
+        if self.milestone_vaults {
+            if portion >= milestone.unreleased_amount() {
+                return Ok(milestone.locked);
+            }
+
+            return Ok((milestone.locked as u128 * portion as u128
+                / milestone.unreleased_amount() as u128) as u64);
+        }
+
+        if portion >= outstanding {
+            return Ok(unreleased);
+        }
+
+        Ok(((unreleased as u128)
+            .checked_mul(portion as u128)
+            .ok_or(TitaErrors::MathOverflow)?
+            / outstanding as u128) as u64)
+    }
+
+    // Releases whatever is left of a milestone and marks it completed
+    pub fn complete_milestone(&mut self, milestone_id: u32, current_time: i64) -> Result<u64> {
+        self.release_milestone_portion(milestone_id, BASIS_POINTS_DENOMINATOR, current_time)
+    }
+
+    // Releases `basis_points` of a milestone's amount, capped at what hasn't
+    // been released yet. The milestone is completed once all of it has been
+    // released. Streaming flows keep the release locked until it vests.
+    pub fn release_milestone_portion(
+        &mut self,
+        milestone_id: u32,
+        basis_points: u16,
+        current_time: i64,
+    ) -> Result<u64> {
+        require!(
+            basis_points > 0 && basis_points <= BASIS_POINTS_DENOMINATOR,
+            TitaErrors::InvalidReleasePercentage
+        );
+
+        if self.sequential_milestones {
+            self.check_previous_milestones_resolved(milestone_id)?;
+        }
+
+        // Settle running streams before this release restarts the milestone's
+        if self.release_mode == ReleaseMode::Streaming {
+            self.vest_streams(current_time)?;
+        }
+
+        let milestone = self
+            .milestones
+            .as_ref()
+            .ok_or(TitaErrors::NotMilestoneFlow)?
+            .iter()
+            .find(|milestone| milestone.id == milestone_id)
+            .ok_or(TitaErrors::MilestoneNotFound)?;
+        let portion = basis_points_share(milestone.amount, basis_points).min(milestone.unreleased_amount());
+
+        let released = self.milestone_share(milestone_id, portion)?;
+        let release_mode = self.release_mode;
+        let milestone_vaults = self.milestone_vaults;
+
+        let milestone = self.milestone_mut(milestone_id)?;
+
+        milestone.released_amount += portion;
+        if milestone_vaults {
+            milestone.locked -= released;
+        }
+        milestone.released = milestone
+            .released
+            .checked_add(released)
+            .ok_or(TitaErrors::MathOverflow)?;
+        milestone.stream_start = current_time;
+        milestone.stream_base = milestone.vested;
+
+        if milestone.unreleased_amount() == 0 {
+            milestone.status = MilestoneStatus::Completed;
+            milestone.challenge_ends_at = None;
+        }
+
+        match release_mode {
+            ReleaseMode::Instant => {
+                milestone.vested = milestone.released;
+
+                self.balance = self
+                    .balance
+                    .checked_sub(released)
+                    .ok_or(TitaErrors::MathOverflow)?;
+                self.available = self
+                    .available
+                    .checked_add(released)
+                    .ok_or(TitaErrors::MathOverflow)?;
+            },
+            ReleaseMode::Streaming => {
+                // Stays in balance, vest_streams moves it over time
+            },
+        }
+
+        Ok(released)
+    }
+
+    // Every milestone before `milestone_id` has to be completed, or have expired
+    pub fn check_previous_milestones_resolved(&self, milestone_id: u32) -> Result<()> {
+        let milestones = self.milestones.as_ref().ok_or(TitaErrors::NotMilestoneFlow)?;
+
+        let previous_resolved = milestones
+            .iter()
+            .take_while(|milestone| milestone.id != milestone_id)
+            .all(|milestone| milestone.is_resolved());
+        require!(previous_resolved, TitaErrors::MilestoneOutOfOrder);
+
+        Ok(())
+    }
+
+    // Gives a missed milestone's share of the locked balance back to the
+    // contributors. It moves into the refund pool, where each outstanding
+    // contribution can claim its pro rata part while the flow carries on.
+    pub fn expire_milestone(&mut self, milestone_id: u32, current_time: i64) -> Result<u64> {
+        // Only the part that hasn't been released goes back
+        let remaining = self
+            .milestones
+            .as_ref()
+            .and_then(|milestones| milestones.iter().find(|milestone| milestone.id == milestone_id))
+            .map_or(0, |milestone| milestone.unreleased_amount());
+        let refunded = self.milestone_share(milestone_id, remaining)?;
+
+        let milestone = self.milestone_mut(milestone_id)?;
+
+        require!(current_time > milestone.deadline, TitaErrors::MilestoneDeadlineNotPassed);
+
+        // An undisputed optimistic submission is waiting to be released
+        require!(milestone.challenge_ends_at.is_none(), TitaErrors::ChallengeWindowActive);
+
+        milestone.status = MilestoneStatus::Expired;
+
+        if !self.return_to_contributors(refunded)? {
+            return Ok(0);
+        }
+
+        let milestone = self.milestone_mut(milestone_id)?;
+        milestone.refund_pool = refunded;
+        milestone.locked = 0;
+
+        Ok(refunded)
+    }
+
+    // Moves `amount` of the locked balance into the refund pool, shared by the
+    // contributions still in the flow. False if there was nothing to move.
+    fn return_to_contributors(&mut self, amount: u64) -> Result<bool> {
+        let outstanding_contributions = self
+            .raised
+            .checked_sub(self.refunded_contributions)
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        if amount == 0 || outstanding_contributions == 0 {
+            return Ok(false);
+        }
+
+        self.balance = self
+            .balance
+            .checked_sub(amount)
+            .ok_or(TitaErrors::MathOverflow)?;
+        self.refund_pool = self
+            .refund_pool
+            .checked_add(amount)
+            .ok_or(TitaErrors::MathOverflow)?;
+        self.refund_per_token = self
+            .refund_per_token
+            .checked_add(amount as u128 * REFUND_PER_TOKEN_SCALE / outstanding_contributions as u128)
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        Ok(true)
+    }
+
+    // Appends a milestone, creating the milestone list on flows without one.
+    // Funds already raised are rebalanced as for an adjustment, its share is
+    // locked out of what hasn't been withdrawn. Later contributions lock their
+    // share as usual.
+    pub fn add_milestone(&mut self, amount: u64, deadline: i64, current_time: i64) -> Result<u32> {
+        require!(amount > 0, TitaErrors::InvalidMilestoneAmount);
+        require!(deadline > current_time, TitaErrors::InvalidMilestoneDeadline);
+
+        if let Some(end_date) = self.end_date {
+            require!(deadline < end_date, TitaErrors::InvalidMilestoneDeadline);
+        }
+
+        let goal = self.goal;
+        let milestones = self.milestones.get_or_insert_with(Vec::new);
+
+        require!(milestones.len() < MAX_MILESTONES, TitaErrors::TooManyMilestones);
+
+        // Milestones can't promise more than the flow is raising
+        let milestone_total = milestones
+            .iter()
+            .try_fold(amount, |sum, milestone| sum.checked_add(milestone.amount))
+            .ok_or(TitaErrors::MathOverflow)?;
+        require!(milestone_total <= goal, TitaErrors::MilestoneTotalMismatch);
+
+        let id = milestones
+            .iter()
+            .map(|milestone| milestone.id)
+            .max()
+            .map_or(Ok(1), |max_id| max_id.checked_add(1).ok_or(TitaErrors::MathOverflow))?;
+
+        milestones.push(Milestone::from(MilestoneInput { id, amount, deadline }));
+        validate_milestone_schedule(milestones.iter().map(|milestone| (milestone.id, milestone.deadline)))?;
+
+        // Back it with funds already raised, like a milestone adjusted upwards
+        self.lock_for_milestone(id, amount)?;
+
+        Ok(id)
+    }
+
+    // Changes an outstanding milestone's amount and/or deadline. Funds already
+    // raised are rebalanced so each outstanding contribution keeps the locked
+    // share a new contribution would get, within what is still available to
+    // lock or still locked to free.
+    pub fn adjust_milestone(
+        &mut self,
+        milestone_id: u32,
+        new_amount: Option<u64>,
+        new_deadline: Option<i64>,
+        current_time: i64,
+    ) -> Result<()> {
+        let goal = self.goal;
+        let end_date = self.end_date;
+        let milestones = self.milestones.as_mut().ok_or(TitaErrors::NotMilestoneFlow)?;
+
+        // Milestones can't promise more than the flow is raising
+        if let Some(amount) = new_amount {
+            require!(amount > 0, TitaErrors::InvalidMilestoneAmount);
+
+            let milestone_total = milestones
+                .iter()
+                .filter(|milestone| milestone.id != milestone_id)
+                .try_fold(amount, |sum, milestone| sum.checked_add(milestone.amount))
+                .ok_or(TitaErrors::MathOverflow)?;
+            require!(milestone_total <= goal, TitaErrors::InvalidMilestoneAdjustment);
+        }
+
+        if let Some(deadline) = new_deadline {
+            require!(deadline > current_time, TitaErrors::InvalidMilestoneDeadline);
+
+            // Check that new deadline is before flow end date if set
+            if let Some(end_date) = end_date {
+                require!(deadline < end_date, TitaErrors::InvalidMilestoneDeadline);
+            }
+        }
+
+        let milestone = milestones
+            .iter_mut()
+            .find(|milestone| milestone.id == milestone_id)
+            .ok_or(TitaErrors::MilestoneNotFound)?;
+
+        // Ensure milestone is still outstanding
+        require!(!milestone.is_completed(), TitaErrors::MilestoneAlreadyCompleted);
+        require!(milestone.status != MilestoneStatus::Expired, TitaErrors::MilestoneExpired);
+
+        let old_amount = milestone.amount;
+
+        if let Some(amount) = new_amount {
+            // Can't go below what has already been released
+            require!(amount >= milestone.released_amount, TitaErrors::InvalidMilestoneAdjustment);
+
+            milestone.amount = amount;
+        }
+        if let Some(deadline) = new_deadline {
+            milestone.deadline = deadline;
+        }
+
+        validate_milestone_schedule(milestones.iter().map(|milestone| (milestone.id, milestone.deadline)))?;
+
+        let new_amount = new_amount.unwrap_or(old_amount);
+
+        if new_amount > old_amount {
+            self.lock_for_milestone(milestone_id, new_amount - old_amount)?;
+        } else if new_amount < old_amount {
+            self.unlock_from_milestone(milestone_id, old_amount - new_amount)?;
+        }
+
+        Ok(())
+    }
+
+    // What raised funds would have locked for `amount` of milestones, so each
+    // outstanding contribution keeps the share a new contribution would get
+    fn rebalance_share(&self, amount: u64) -> Result<u64> {
+        let outstanding_contributions = self
+            .raised
+            .checked_sub(self.refunded_contributions)
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        Ok((amount as u128 * outstanding_contributions as u128 / self.goal as u128) as u64)
+    }
+
+    // Locks more of what hasn't been withdrawn yet behind a milestone whose
+    // amount grew by `increase`
+    fn lock_for_milestone(&mut self, milestone_id: u32, increase: u64) -> Result<u64> {
+        // All-or-nothing flows hold everything until they settle, which does the split
+        if self.funding_mode == FundingMode::AllOrNothing && self.flow_status != FlowStatus::Completed {
+            return Ok(0);
+        }
+
+        let locked = self.rebalance_share(increase)?.min(self.available);
+
+        self.available -= locked;
+        self.balance = self
+            .balance
+            .checked_add(locked)
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        if self.milestone_vaults {
+            let milestone = self.milestone_mut(milestone_id)?;
+            milestone.locked = milestone
+                .locked
+                .checked_add(locked)
+                .ok_or(TitaErrors::MathOverflow)?;
+        }
+
+        Ok(locked)
+    }
+
+    // Frees part of the locked balance for a milestone whose amount shrank by
+    // `decrease`, never funds already streaming
+    fn unlock_from_milestone(&mut self, milestone_id: u32, decrease: u64) -> Result<u64> {
+        if self.funding_mode == FundingMode::AllOrNothing && self.flow_status != FlowStatus::Completed {
+            return Ok(0);
+        }
+
+        let unreleased = if self.milestone_vaults {
+            self.milestone_mut(milestone_id)?.locked
+        } else {
+            self.balance
+                .checked_sub(self.locked_in_streams()?)
+                .ok_or(TitaErrors::MathOverflow)?
+        };
+        let unlocked = self.rebalance_share(decrease)?.min(unreleased);
+
+        self.balance -= unlocked;
+        self.available = self
+            .available
+            .checked_add(unlocked)
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        if self.milestone_vaults {
+            self.milestone_mut(milestone_id)?.locked -= unlocked;
+        }
+
+        Ok(unlocked)
+    }
+
+    // Drops an outstanding milestone. Its locked share is freed as for an
+    // adjustment down to nothing, what rounding leaves stays with the remaining
+    // milestones. If none are left the whole locked balance becomes available.
+    pub fn remove_milestone(&mut self, milestone_id: u32) -> Result<u64> {
+        let milestones = self.milestones.as_ref().ok_or(TitaErrors::NotMilestoneFlow)?;
+
+        let index = milestones
+            .iter()
+            .position(|milestone| milestone.id == milestone_id)
+            .ok_or(TitaErrors::MilestoneNotFound)?;
+
+        // Released or refunded funds can't be taken back
+        require!(!milestones[index].is_completed(), TitaErrors::MilestoneAlreadyCompleted);
+        require!(milestones[index].status != MilestoneStatus::Expired, TitaErrors::MilestoneExpired);
+        require!(milestones[index].released_amount == 0, TitaErrors::MilestonePartiallyReleased);
+
+        let mut unlocked = self.unlock_from_milestone(milestone_id, milestones[index].amount)?;
+
+        let milestones = self.milestones.as_mut().ok_or(TitaErrors::NotMilestoneFlow)?;
+        let removed = milestones.remove(index);
+        let remaining_outstanding = milestones.iter().any(|milestone| !milestone.is_resolved());
+
+        if milestones.is_empty() {
+            self.milestones = None;
+        }
+
+        if remaining_outstanding {
+            // Vaulted funds are spread over the remaining milestones' vaults
+            if self.milestone_vaults {
+                self.allocate_to_vaults(removed.locked)?;
+            }
+
+            return Ok(unlocked);
+        }
+
+        // All-or-nothing flows hold everything until they settle, which does the split
+        if self.funding_mode == FundingMode::AllOrNothing && self.flow_status != FlowStatus::Completed {
+            return Ok(unlocked);
+        }
+
+        // Nothing left to lock funds for
+        let rest = self
+            .balance
+            .checked_sub(self.locked_in_streams()?)
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        self.balance -= rest;
+        self.available = self
+            .available
+            .checked_add(rest)
+            .ok_or(TitaErrors::MathOverflow)?;
+        unlocked += rest;
+
+        Ok(unlocked)
+    }
+
+    // Each contribution's expired refund rounds down by less than a token, so
+    // a pool smaller than the number of contributions still in the flow is
+    // what's left once they've all claimed
+    pub fn refund_pool_is_dust(&self) -> bool {
+        let outstanding_contributions = self.contributor_count.saturating_sub(self.refunds_count);
+
+        self.refund_pool < u64::from(outstanding_contributions.max(1))
+    }
+
+    // Pays `amount` of expired milestone refunds out of the refund pool
+    pub fn claim_expired_refund(&mut self, amount: u64) -> Result<()> {
+        self.refund_pool = self
+            .refund_pool
+            .checked_sub(amount)
+            .ok_or(TitaErrors::InsufficientFunds)?;
+        self.total_refunded = self
+            .total_refunded
+            .checked_add(amount)
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        Ok(())
+    }
+
+    // Released milestone funds that haven't vested yet, still held in balance
+    pub fn locked_in_streams(&self) -> Result<u64> {
+        let Some(milestones) = &self.milestones else {
+            return Ok(0);
+        };
+
+        milestones
+            .iter()
+            .try_fold(0u64, |sum, milestone| {
+                sum.checked_add(milestone.released.checked_sub(milestone.vested)?)
+            })
+            .ok_or(TitaErrors::MathOverflow.into())
+    }
+
+    // Moves whatever has vested by `current_time` from balance to available.
+    // Vesting stops once the flow is canceled so the rest can be refunded.
+    pub fn vest_streams(&mut self, current_time: i64) -> Result<u64> {
+        if self.flow_status == FlowStatus::Canceled {
+            return Ok(0);
+        }
+
+        let Some(milestones) = self.milestones.as_mut() else {
+            return Ok(0);
+        };
+
+        let mut newly_vested = 0u64;
+        for milestone in milestones.iter_mut() {
+            let vested = milestone.vested_at(current_time);
+            if vested > milestone.vested {
+                newly_vested = newly_vested
+                    .checked_add(vested - milestone.vested)
+                    .ok_or(TitaErrors::MathOverflow)?;
+                milestone.vested = vested;
+            }
+        }
+
+        self.balance = self
+            .balance
+            .checked_sub(newly_vested)
+            .ok_or(TitaErrors::MathOverflow)?;
+        self.available = self
+            .available
+            .checked_add(newly_vested)
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        Ok(newly_vested)
+    }
+
+    // Refund owed for a contribution of `contribution_amount`. Everything not
+    // yet withdrawn is shared pro rata between the contributions not yet refunded,
+    // so every contributor gets the same fraction back and the last one clears the pool.
+    pub fn refund_share(&self, contribution_amount: u64) -> Result<u64> {
+        let refundable_pool = self
+            .balance
+            .checked_add(self.available)
+            .ok_or(TitaErrors::MathOverflow)?;
+        let outstanding_contributions = self
+            .raised
+            .checked_sub(self.refunded_contributions)
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        // Avoid division by zero
+        if outstanding_contributions == 0 {
+            return Ok(0);
+        }
+
+        let share = (contribution_amount as u128)
+            .checked_mul(refundable_pool as u128)
+            .ok_or(TitaErrors::MathOverflow)?
+            / outstanding_contributions as u128;
+
+        Ok(share as u64)
+    }
+
+    // Takes `amount` out of the flow's unwithdrawn funds, locked funds first,
+    // for a refunded contribution of `contribution_amount`
+    pub fn deduct_refund(&mut self, amount: u64, contribution_amount: u64) -> Result<()> {
+        let from_balance = amount.min(self.balance);
+        let from_available = amount - from_balance;
+
+        self.balance -= from_balance;
+        self.available = self
+            .available
+            .checked_sub(from_available)
+            .ok_or(TitaErrors::InsufficientFunds)?;
+        self.total_refunded = self
+            .total_refunded
+            .checked_add(amount)
+            .ok_or(TitaErrors::MathOverflow)?;
+        self.refunded_contributions = self
+            .refunded_contributions
+            .checked_add(contribution_amount)
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        Ok(())
+    }
+
+    // Splits `amount` between the payout recipients and records what each one
+    // is owed. Shares are worked out on the running total ever distributed, so
+    // rounding dust carries over to the next payout instead of being lost, and
+    // the last recipient takes whatever remains of this payout.
+    pub fn allocate_distribution(&mut self, amount: u64) -> Result<Vec<u64>> {
+        let splits = self.splits.as_mut().ok_or(TitaErrors::NoPayoutSplits)?;
+
+        let distributed_total = splits
+            .iter()
+            .try_fold(0u64, |sum, split| sum.checked_add(split.distributed))
+            .and_then(|sum| sum.checked_add(amount))
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        let last = splits.len() - 1;
+        let mut remaining = amount;
+        let mut payouts = Vec::with_capacity(splits.len());
+
+        for (index, split) in splits.iter_mut().enumerate() {
+            let payout = if index == last {
+                remaining
+            } else {
+                basis_points_share(distributed_total, split.basis_points)
+                    .saturating_sub(split.distributed)
+                    .min(remaining)
+            };
+
+            remaining -= payout;
+            split.distributed = split
+                .distributed
+                .checked_add(payout)
+                .ok_or(TitaErrors::MathOverflow)?;
+            payouts.push(payout);
+        }
+
+        Ok(payouts)
+    }
+
+    // Escrow invariant, every raised token must be accounted for exactly once
+    pub fn check_balances(&self) -> Result<()> {
+        let accounted = self
+            .balance
+            .checked_add(self.available)
+            .and_then(|sum| sum.checked_add(self.withdrawn))
+            .and_then(|sum| sum.checked_add(self.refund_pool))
+            .and_then(|sum| sum.checked_add(self.total_refunded))
+            .ok_or(TitaErrors::MathOverflow)?;
+
+        require!(accounted == self.raised, TitaErrors::BalanceMismatch);
+
+        if self.milestone_vaults {
+            // Vaults hold every locked token that isn't streaming, canceled flows refund from them
+            if self.flow_status != FlowStatus::Canceled {
+                let vaulted = self
+                    .milestones
+                    .iter()
+                    .flatten()
+                    .try_fold(self.locked_in_streams()?, |sum, milestone| sum.checked_add(milestone.locked))
+                    .ok_or(TitaErrors::MathOverflow)?;
+
+                require!(vaulted == self.balance, TitaErrors::BalanceMismatch);
+            }
+
+            // Whatever was released from a vault has to have reached flow_ta,
+            // which withdrawals and refunds are paid from
+            let vault_targets = self
+                .milestones
+                .iter()
+                .flatten()
+                .try_fold(0u64, |sum, milestone| sum.checked_add(self.vault_target(milestone.id)))
+                .ok_or(TitaErrors::MathOverflow)?;
+
+            require!(self.vault_holdings <= vault_targets, TitaErrors::VaultsNotSynced);
+        }
+
+        Ok(())
+    }
+
+    // What a milestone's vault should be holding, nothing once the flow is
+    // canceled or the milestone has been removed
+    pub fn vault_target(&self, milestone_id: u32) -> u64 {
+        if self.flow_status == FlowStatus::Canceled {
+            return 0;
+        }
+
+        self.milestones
+            .iter()
+            .flatten()
+            .find(|milestone| milestone.id == milestone_id)
+            .map_or(0, |milestone| milestone.locked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_flow(goal: u64) -> Flow {
+        Flow {
+            flow_id: String::from("test-flow"),
+            creator: Pubkey::default(),
+            beneficiary: Pubkey::default(),
+            token_mint: Pubkey::default(),
+            kind: FlowKind::Raise,
+            funding_mode: FundingMode::Flexible,
+            release_mode: ReleaseMode::Instant,
+            approval_mode: ApprovalMode::Governance,
+            sequential_milestones: false,
+            milestone_vaults: false,
+            vault_holdings: 0,
+            goal,
+            caps: None,
+            raised: 0,
+            balance: 0,
+            available: 0,
+            withdrawn: 0,
+            refund_pool: 0,
+            flow_ta: Pubkey::default(),
+            start_date: None,
+            end_date: None,
+            flow_status: FlowStatus::Active,
+            contributor_count: 0,
+            milestones: None,
+            splits: None,
+            proposal_count: 0,
+            voting_power_model: VotingPowerModel::TokenWeighted,
+            governance: GovernanceConfig::default(),
+            active_proposal: None,
+            total_voting_weight: 0,
+            total_refunded: 0,
+            refunded_contributions: 0,
+            refunds_count: 0,
+            refund_per_token: 0,
+            bump: 255,
+        }
+    }
+
+    fn milestone(id: u32, amount: u64, deadline: i64) -> Milestone {
+        MilestoneInput { id, amount, deadline }.into()
+    }
+
+    fn split(basis_points: u16, distributed: u64) -> PayoutSplit {
+        PayoutSplit { recipient: Pubkey::new_unique(), basis_points, distributed }
+    }
+
+    // Milestones of 200 and 400 out of a 1000 goal, 500 raised so far
+    fn raised_flow(milestone_vaults: bool) -> Flow {
+        let mut flow = test_flow(1_000);
+        flow.milestone_vaults = milestone_vaults;
+        flow.milestones = Some(vec![milestone(1, 200, 100), milestone(2, 400, 200)]);
+        flow.record_contribution(500).unwrap();
+        flow
+    }
+
+    // Two milestones of 500 making up a fully raised 1000 goal
+    fn funded_flow(milestone_vaults: bool) -> Flow {
+        let mut flow = test_flow(1_000);
+        flow.milestone_vaults = milestone_vaults;
+        flow.milestones = Some(vec![milestone(1, 500, 100), milestone(2, 500, 200)]);
+        flow.record_contribution(1_000).unwrap();
+        flow
+    }
+
+    #[test]
+    fn record_contribution_all_or_nothing_locks_everything() {
+        let mut flow = test_flow(1_000);
+        flow.funding_mode = FundingMode::AllOrNothing;
+
+        assert!(flow.record_contribution(500).unwrap().is_empty());
+        assert_eq!((flow.raised, flow.available, flow.balance), (500, 0, 500));
+    }
+
+    #[test]
+    fn unlocked_share_without_milestones_is_everything() {
+        let flow = test_flow(1_000);
+        assert_eq!(flow.unlocked_share(500).unwrap(), 500);
+    }
+
+    #[test]
+    fn unlocked_share_leaves_milestone_part_locked() {
+        let mut flow = test_flow(1_000);
+        flow.milestones = Some(vec![milestone(1, 200, 100), milestone(2, 400, 200)]);
+        assert_eq!(flow.unlocked_share(500).unwrap(), 200);
+
+        // Resolved milestones don't need funds anymore
+        flow.milestones.as_mut().unwrap()[0].status = MilestoneStatus::Completed;
+        assert_eq!(flow.unlocked_share(500).unwrap(), 300);
+
+        // Milestones covering the whole goal lock everything
+        flow.milestones = Some(vec![milestone(1, 1_000, 100), milestone(2, 400, 200)]);
+        assert_eq!(flow.unlocked_share(500).unwrap(), 0);
+    }
+
+    #[test]
+    fn record_contribution_splits_available_and_locked() {
+        let mut flow = test_flow(1_000);
+        flow.milestones = Some(vec![milestone(1, 200, 100), milestone(2, 400, 200)]);
+
+        assert!(flow.record_contribution(500).unwrap().is_empty());
+        assert_eq!((flow.raised, flow.available, flow.balance), (500, 200, 300));
+        flow.check_balances().unwrap();
+    }
+
+    #[test]
+    fn allocate_distribution_tracks_shares_of_running_total() {
+        let mut flow = test_flow(1_000);
+        flow.splits = Some(vec![split(3_333, 0), split(3_333, 0), split(3_334, 0)]);
+
+        for _ in 0..10 {
+            let payouts = flow.allocate_distribution(7).unwrap();
+            assert_eq!(payouts.iter().sum::<u64>(), 7);
+        }
+
+        // Rounding dust carried over, each share matches the 70 paid out overall
+        let distributed: Vec<u64> = flow.splits.unwrap().iter().map(|split| split.distributed).collect();
+        assert_eq!(distributed, vec![23, 23, 24]);
+    }
+
+    #[test]
+    fn allocate_distribution_never_pays_out_more_than_amount() {
+        let mut flow = test_flow(1_000);
+        flow.splits = Some(vec![split(5_000, 0), split(5_000, 100)]);
+
+        // The first recipient is owed 55 but only 10 is being paid out
+        assert_eq!(flow.allocate_distribution(10).unwrap(), vec![10, 0]);
+    }
+
+    #[test]
+    fn allocate_distribution_requires_splits() {
+        let mut flow = test_flow(1_000);
+        assert_eq!(flow.allocate_distribution(10).unwrap_err(), TitaErrors::NoPayoutSplits.into());
+    }
+
+    #[test]
+    fn vested_at_streams_linearly_until_deadline() {
+        let mut streaming = milestone(1, 100, 1_000);
+        streaming.released = 100;
+
+        assert_eq!(streaming.vested_at(0), 0);
+        assert_eq!(streaming.vested_at(500), 50);
+        assert_eq!(streaming.vested_at(999), 99);
+        assert_eq!(streaming.vested_at(1_000), 100);
+        assert_eq!(streaming.vested_at(2_000), 100);
+    }
+
+    #[test]
+    fn vested_at_only_streams_the_latest_release() {
+        let mut streaming = milestone(1, 100, 1_000);
+        streaming.released = 100;
+        streaming.stream_base = 40;
+        streaming.stream_start = 500;
+
+        assert_eq!(streaming.vested_at(400), 40);
+        assert_eq!(streaming.vested_at(500), 40);
+        assert_eq!(streaming.vested_at(750), 70);
+        assert_eq!(streaming.vested_at(1_000), 100);
+    }
+
+    #[test]
+    fn vested_at_release_after_deadline_vests_right_away() {
+        let mut late = milestone(1, 100, 1_000);
+        late.released = 100;
+        late.stream_start = 1_500;
+
+        assert_eq!(late.vested_at(1_200), 100);
+    }
+
+    #[test]
+    fn return_to_contributors_moves_balance_into_refund_pool() {
+        let mut flow = test_flow(1_000);
+        flow.raised = 1_000;
+        flow.balance = 1_000;
+
+        assert!(flow.return_to_contributors(200).unwrap());
+        assert_eq!((flow.balance, flow.refund_pool), (800, 200));
+        assert_eq!(flow.refund_per_token, REFUND_PER_TOKEN_SCALE / 5);
+        flow.check_balances().unwrap();
+    }
+
+    #[test]
+    fn return_to_contributors_shares_between_outstanding_contributions() {
+        let mut flow = test_flow(1_000);
+        flow.raised = 1_000;
+        flow.balance = 500;
+        flow.total_refunded = 500;
+        flow.refunded_contributions = 500;
+
+        assert!(flow.return_to_contributors(100).unwrap());
+        assert_eq!(flow.refund_per_token, REFUND_PER_TOKEN_SCALE / 5);
+    }
+
+    #[test]
+    fn return_to_contributors_with_nothing_to_move() {
+        let mut flow = test_flow(1_000);
+        flow.raised = 1_000;
+        flow.balance = 1_000;
+        assert!(!flow.return_to_contributors(0).unwrap());
+
+        // Nobody left to share it with
+        flow.refunded_contributions = 1_000;
+        assert!(!flow.return_to_contributors(200).unwrap());
+        assert_eq!((flow.balance, flow.refund_pool, flow.refund_per_token), (1_000, 0, 0));
+    }
+
+    #[test]
+    fn record_contribution_fills_milestone_vaults() {
+        let mut flow = test_flow(1_000);
+        flow.milestone_vaults = true;
+        flow.milestones = Some(vec![milestone(1, 200, 100), milestone(2, 400, 200)]);
+
+        assert_eq!(flow.record_contribution(500).unwrap(), vec![100, 200]);
+
+        let locked: Vec<u64> = flow.milestones.as_ref().unwrap().iter().map(|milestone| milestone.locked).collect();
+        assert_eq!(locked, vec![100, 200]);
+        flow.check_balances().unwrap();
+
+        // 5 locks 3, the last milestone takes the rounding remainder
+        assert_eq!(flow.record_contribution(5).unwrap(), vec![1, 2]);
+        flow.check_balances().unwrap();
+    }
+
+    #[test]
+    fn adjust_milestone_up_locks_more_of_raised_funds() {
+        let mut flow = raised_flow(false);
+
+        flow.adjust_milestone(1, Some(300), None, 0).unwrap();
+        assert_eq!((flow.available, flow.balance), (150, 350));
+        flow.check_balances().unwrap();
+
+        // The same split a contribution made now would get
+        assert_eq!(flow.unlocked_share(500).unwrap(), 150);
+    }
+
+    #[test]
+    fn adjust_milestone_down_frees_raised_funds() {
+        let mut flow = raised_flow(false);
+
+        flow.adjust_milestone(2, Some(100), None, 0).unwrap();
+        assert_eq!((flow.available, flow.balance), (350, 150));
+        flow.check_balances().unwrap();
+
+        assert_eq!(flow.unlocked_share(500).unwrap(), 350);
+    }
+
+    #[test]
+    fn adjust_milestone_moves_vaulted_funds() {
+        let mut flow = raised_flow(true);
+
+        flow.adjust_milestone(1, Some(300), None, 0).unwrap();
+        assert_eq!((flow.vault_target(1), flow.vault_target(2)), (150, 200));
+        flow.check_balances().unwrap();
+
+        flow.adjust_milestone(2, Some(100), None, 0).unwrap();
+        assert_eq!((flow.vault_target(1), flow.vault_target(2)), (150, 50));
+        assert_eq!((flow.available, flow.balance), (300, 200));
+        flow.check_balances().unwrap();
+    }
+
+    #[test]
+    fn adjust_milestone_only_locks_what_is_still_available() {
+        let mut flow = raised_flow(false);
+        flow.withdrawn = 180;
+        flow.available = 20;
+
+        flow.adjust_milestone(1, Some(300), None, 0).unwrap();
+        assert_eq!((flow.available, flow.balance), (0, 320));
+        flow.check_balances().unwrap();
+    }
+
+    #[test]
+    fn adjust_milestone_deadline_leaves_funds_alone() {
+        let mut flow = raised_flow(false);
+
+        flow.adjust_milestone(1, None, Some(150), 0).unwrap();
+        assert_eq!(flow.milestones.as_ref().unwrap()[0].deadline, 150);
+        assert_eq!((flow.available, flow.balance), (200, 300));
+    }
+
+    #[test]
+    fn adjust_milestone_rejects_invalid_changes() {
+        let mut flow = raised_flow(false);
+
+        // Over the goal
+        assert_eq!(
+            flow.adjust_milestone(1, Some(700), None, 0).unwrap_err(),
+            TitaErrors::InvalidMilestoneAdjustment.into()
+        );
+
+        // Below what has been released
+        flow.release_milestone_portion(1, 5_000, 10).unwrap();
+        assert_eq!(
+            flow.adjust_milestone(1, Some(50), None, 10).unwrap_err(),
+            TitaErrors::InvalidMilestoneAdjustment.into()
+        );
+
+        // Out of deadline order
+        assert_eq!(
+            flow.adjust_milestone(1, None, Some(250), 10).unwrap_err(),
+            TitaErrors::MilestoneDeadlinesNotIncreasing.into()
+        );
+        flow.check_balances().unwrap();
+    }
+
+    #[test]
+    fn release_milestone_portion_then_the_rest() {
+        let mut flow = funded_flow(false);
+
+        assert_eq!(flow.release_milestone_portion(1, 4_000, 10).unwrap(), 200);
+        assert_eq!((flow.available, flow.balance), (200, 800));
+        let released = &flow.milestones.as_ref().unwrap()[0];
+        assert_eq!(released.released_amount, 200);
+        assert!(released.status == MilestoneStatus::Pending);
+        flow.check_balances().unwrap();
+
+        assert_eq!(flow.complete_milestone(1, 20).unwrap(), 300);
+        assert_eq!((flow.available, flow.balance), (500, 500));
+        assert!(flow.milestones.as_ref().unwrap()[0].is_completed());
+        flow.check_balances().unwrap();
+    }
+
+    #[test]
+    fn release_milestone_portion_from_its_vault() {
+        let mut flow = funded_flow(true);
+
+        assert_eq!(flow.release_milestone_portion(1, 4_000, 10).unwrap(), 200);
+        assert_eq!((flow.vault_target(1), flow.vault_target(2)), (300, 500));
+        flow.check_balances().unwrap();
+
+        assert_eq!(flow.complete_milestone(1, 20).unwrap(), 300);
+        assert_eq!((flow.vault_target(1), flow.vault_target(2)), (0, 500));
+        flow.check_balances().unwrap();
+    }
+
+    #[test]
+    fn release_milestone_portion_caps_at_what_is_left() {
+        let mut flow = funded_flow(false);
+
+        assert_eq!(
+            flow.release_milestone_portion(1, 0, 10).unwrap_err(),
+            TitaErrors::InvalidReleasePercentage.into()
+        );
+
+        flow.release_milestone_portion(1, 6_000, 10).unwrap();
+        assert_eq!(flow.release_milestone_portion(1, 6_000, 20).unwrap(), 200);
+        assert!(flow.milestones.as_ref().unwrap()[0].is_completed());
+        assert_eq!(
+            flow.release_milestone_portion(1, 1_000, 30).unwrap_err(),
+            TitaErrors::MilestoneAlreadyCompleted.into()
+        );
+    }
+
+    #[test]
+    fn expire_milestone_after_partial_release_refunds_the_rest() {
+        let mut flow = funded_flow(false);
+        flow.release_milestone_portion(1, 4_000, 10).unwrap();
+
+        assert_eq!(
+            flow.expire_milestone(1, 100).unwrap_err(),
+            TitaErrors::MilestoneDeadlineNotPassed.into()
+        );
+
+        assert_eq!(flow.expire_milestone(1, 101).unwrap(), 300);
+        assert_eq!((flow.available, flow.balance, flow.refund_pool), (200, 500, 300));
+        assert_eq!(flow.refund_per_token, 3 * REFUND_PER_TOKEN_SCALE / 10);
+
+        let expired = &flow.milestones.as_ref().unwrap()[0];
+        assert!(expired.status == MilestoneStatus::Expired);
+        assert_eq!(expired.refund_pool, 300);
+        flow.check_balances().unwrap();
+    }
+
+    #[test]
+    fn expire_milestone_empties_its_vault() {
+        let mut flow = funded_flow(true);
+        flow.release_milestone_portion(1, 4_000, 10).unwrap();
+
+        assert_eq!(flow.expire_milestone(1, 101).unwrap(), 300);
+        assert_eq!((flow.vault_target(1), flow.vault_target(2)), (0, 500));
+        flow.check_balances().unwrap();
+    }
+
+    #[test]
+    fn refunds_share_unwithdrawn_funds_pro_rata() {
+        let mut flow = funded_flow(false);
+        flow.release_milestone_portion(1, 4_000, 10).unwrap();
+        flow.flow_status = FlowStatus::Canceled;
+        assert_eq!(flow.refund_share(400).unwrap(), 400);
+
+        // Part of the release was withdrawn before the cancellation
+        flow.available -= 100;
+        flow.withdrawn += 100;
+
+        // Contributions of 400 and 600, each gets 90% back
+        let first = flow.refund_share(400).unwrap();
+        assert_eq!(first, 360);
+        flow.deduct_refund(first, 400).unwrap();
+        assert_eq!((flow.available, flow.balance), (100, 440));
+        flow.check_balances().unwrap();
+
+        // The last refund clears what's left, locked funds first
+        let last = flow.refund_share(600).unwrap();
+        assert_eq!(last, 540);
+        flow.deduct_refund(last, 600).unwrap();
+        assert_eq!((flow.available, flow.balance), (0, 0));
+        assert_eq!((flow.total_refunded, flow.refunded_contributions), (900, 1_000));
+        flow.check_balances().unwrap();
+        assert_eq!(flow.refund_share(100).unwrap(), 0);
+    }
+
+    #[test]
+    fn refund_share_leaves_out_the_expired_refund_pool() {
+        let mut flow = funded_flow(false);
+        flow.expire_milestone(1, 101).unwrap();
+        flow.flow_status = FlowStatus::Canceled;
+
+        // The expired milestone's half is claimed separately
+        assert_eq!(flow.refund_share(1_000).unwrap(), 500);
+    }
+
+    #[test]
+    fn check_balances_requires_released_vault_funds_in_flow_ta() {
+        let mut flow = test_flow(1_000);
+        flow.milestone_vaults = true;
+        flow.milestones = Some(vec![milestone(1, 200, 100), milestone(2, 400, 200)]);
+        flow.vault_holdings = flow.record_contribution(500).unwrap().iter().sum();
+        flow.check_balances().unwrap();
+
+        // Released but still sitting in the milestone's vault
+        assert_eq!(flow.complete_milestone(1, 50).unwrap(), 100);
+        assert_eq!(flow.check_balances().unwrap_err(), TitaErrors::VaultsNotSynced.into());
+
+        flow.vault_holdings -= 100;
+        flow.check_balances().unwrap();
+
+        // Canceled flows refund from flow_ta, every vault has to be emptied
+        flow.flow_status = FlowStatus::Canceled;
+        assert_eq!(flow.check_balances().unwrap_err(), TitaErrors::VaultsNotSynced.into());
+
+        flow.vault_holdings = 0;
+        flow.check_balances().unwrap();
+    }
+
+    #[test]
+    fn accepted_contribution_without_caps_takes_everything() {
+        let flow = test_flow(1_000);
+        assert_eq!(flow.accepted_contribution(5_000, 0).unwrap(), 5_000);
+    }
+
+    #[test]
+    fn accepted_contribution_enforces_wallet_limits() {
+        let mut flow = test_flow(1_000);
+        flow.caps = Some(ContributionCaps { hard_cap: None, min_contribution: 100, max_contribution: Some(500) });
+
+        assert_eq!(flow.accepted_contribution(100, 0).unwrap(), 100);
+        assert_eq!(flow.accepted_contribution(50, 100).unwrap(), 50);
+        assert_eq!(flow.accepted_contribution(50, 0).unwrap_err(), TitaErrors::ContributionBelowMinimum.into());
+        assert_eq!(flow.accepted_contribution(600, 0).unwrap_err(), TitaErrors::ContributionAboveMaximum.into());
+        assert_eq!(flow.accepted_contribution(200, 400).unwrap_err(), TitaErrors::ContributionAboveMaximum.into());
+    }
+
+    #[test]
+    fn accepted_contribution_trims_to_hard_cap() {
+        let mut flow = test_flow(1_000);
+        flow.caps = Some(ContributionCaps { hard_cap: Some(1_200), min_contribution: 100, max_contribution: None });
+        flow.raised = 1_150;
+
+        // The last contribution may be under the minimum, the rest stays with the contributor
+        assert_eq!(flow.accepted_contribution(300, 0).unwrap(), 50);
+
+        flow.raised = 1_200;
+        assert_eq!(flow.accepted_contribution(300, 0).unwrap_err(), TitaErrors::HardCapReached.into());
+    }
+
+    #[test]
+    fn accepted_contribution_hard_cap_defaults_to_goal() {
+        let mut flow = test_flow(1_000);
+        flow.caps = Some(ContributionCaps { hard_cap: None, min_contribution: 0, max_contribution: None });
+        flow.raised = 900;
+
+        assert_eq!(flow.accepted_contribution(300, 0).unwrap(), 100);
+    }
+
+    #[test]
+    fn add_milestone_locks_its_share_of_raised_funds() {
+        let mut flow = test_flow(1_000);
+        flow.record_contribution(500).unwrap();
+
+        assert_eq!(flow.add_milestone(400, 100, 0).unwrap(), 1);
+        assert_eq!((flow.available, flow.balance), (300, 200));
+        flow.check_balances().unwrap();
+
+        // The same split a contribution made now would get
+        assert_eq!(flow.unlocked_share(500).unwrap(), 300);
+    }
+
+    #[test]
+    fn add_milestone_fills_its_vault() {
+        let mut flow = test_flow(1_000);
+        flow.milestone_vaults = true;
+        flow.milestones = Some(vec![milestone(1, 200, 100)]);
+        flow.record_contribution(500).unwrap();
+
+        assert_eq!(flow.add_milestone(400, 200, 0).unwrap(), 2);
+        assert_eq!(flow.vault_target(1), 100);
+        assert_eq!(flow.vault_target(2), 200);
+        assert_eq!((flow.available, flow.balance), (200, 300));
+        flow.check_balances().unwrap();
+    }
+
+    #[test]
+    fn remove_milestone_frees_its_share() {
+        let mut flow = test_flow(1_000);
+        flow.milestones = Some(vec![milestone(1, 200, 100), milestone(2, 400, 200)]);
+        flow.record_contribution(500).unwrap();
+
+        assert_eq!(flow.remove_milestone(1).unwrap(), 100);
+        assert_eq!((flow.available, flow.balance), (300, 200));
+        flow.check_balances().unwrap();
+
+        // As if it had never been there
+        assert_eq!(flow.unlocked_share(500).unwrap(), 300);
+    }
+
+    #[test]
+    fn remove_milestone_matches_adjusting_it_away() {
+        let vaulted_flow = || {
+            let mut flow = test_flow(1_000);
+            flow.milestone_vaults = true;
+            flow.milestones = Some(vec![milestone(1, 200, 100), milestone(2, 400, 200)]);
+            flow.record_contribution(500).unwrap();
+            flow
+        };
+
+        let mut removed = vaulted_flow();
+        removed.remove_milestone(1).unwrap();
+        assert_eq!(removed.vault_target(1), 0);
+        assert_eq!(removed.vault_target(2), 200);
+        removed.check_balances().unwrap();
+
+        // Adjusting down to a single token frees all but that token's share
+        let mut adjusted = vaulted_flow();
+        adjusted.adjust_milestone(1, Some(1), None, 0).unwrap();
+        adjusted.check_balances().unwrap();
+
+        assert_eq!((removed.available, adjusted.available), (300, 299));
+    }
+
+    #[test]
+    fn remove_last_milestone_unlocks_everything() {
+        let mut flow = test_flow(1_000);
+        flow.milestones = Some(vec![milestone(1, 400, 100)]);
+        flow.record_contribution(500).unwrap();
+
+        assert_eq!(flow.remove_milestone(1).unwrap(), 200);
+        assert_eq!((flow.available, flow.balance), (500, 0));
+        assert!(flow.milestones.is_none());
+        flow.check_balances().unwrap();
+    }
+
+    #[test]
+    fn remove_milestone_keeps_all_or_nothing_escrow() {
+        let mut flow = test_flow(1_000);
+        flow.funding_mode = FundingMode::AllOrNothing;
+        flow.milestones = Some(vec![milestone(1, 400, 100)]);
+        flow.record_contribution(500).unwrap();
+
+        assert_eq!(flow.remove_milestone(1).unwrap(), 0);
+        assert_eq!((flow.available, flow.balance), (0, 500));
+        flow.check_balances().unwrap();
+
+        // Settling can still release what it unlocks
+        assert!(flow.unlocked_share(flow.raised).unwrap() <= flow.balance);
+    }
+}
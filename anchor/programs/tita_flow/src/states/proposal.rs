@@ -17,6 +17,20 @@ pub enum ProposalType {
     FlowFundingExtension {
         new_end_date: i64,
     },
+    ChangeBeneficiary {
+        new_beneficiary: Pubkey,
+    },
+    AddMilestone {
+        amount: u64,
+        deadline: i64,
+    },
+    RemoveMilestone {
+        milestone_id: u32,
+    },
+    PartialMilestoneRelease {
+        milestone_id: u32,
+        basis_points: u16,
+    },
 }
 
 
@@ -29,7 +43,7 @@ pub enum ProposalStatus {
     Canceled
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, InitSpace)]
 pub enum VoteType {
     For,
     Against,
@@ -49,7 +63,9 @@ pub struct Proposal {
     pub votes_for: u64,              // Total votes in favor
     pub votes_against: u64,          // Total votes against
     pub votes_abstain: u64,          // Total abstained votes
-    pub total_eligible_votes: u64,   // Total possible votes (sum of all contributions)
+    pub total_eligible_votes: u64,   // Total possible votes at the snapshot, per the flow's voting model
+    pub snapshot_slot: u64,          // Slot at which voting power was snapshotted
+    pub snapshot_timestamp: i64,     // Time at which voting power was snapshotted
     pub executed_at: Option<i64>,    // When the proposal was executed, if applicable
     pub quorum_percentage: u16,      // Required participation as percentage (basis points)
     pub approval_percentage: u16,    // Required approval as percentage (basis points)
@@ -68,6 +84,20 @@ impl Proposal {
         current_time >= self.voting_starts_at && current_time <= self.voting_ends_at
     }
     
+    // Votes cast so far in any direction
+    pub fn total_votes(&self) -> Result<u64> {
+        self.votes_for
+            .checked_add(self.votes_against)
+            .and_then(|votes| votes.checked_add(self.votes_abstain))
+            .ok_or(error!(TitaErrors::MathOverflow))
+    }
+    
+    // At least one vote for it under a real approval threshold. Without this
+    // an empty tally meets a zero quorum and zero approval.
+    pub fn has_support(&self) -> bool {
+        self.votes_for > 0 && self.approval_percentage > 0
+    }
+
     pub fn can_vote(&self, current_time: i64) -> bool {
         self.status == ProposalStatus::Active && 
         current_time >= self.voting_starts_at && 
@@ -93,13 +123,15 @@ impl Proposal {
         
         // Check if voting period is over
         if current_time > self.voting_ends_at {
+            // Nothing passes without support
+            if !self.has_support() {
+                self.status = ProposalStatus::Failed;
+                return Ok(());
+            }
+
             // Check quorum
-            let total_votes = self.votes_for + self.votes_against + self.votes_abstain;
-            let quorum_threshold = self.total_eligible_votes
-                .checked_mul(self.quorum_percentage as u64)
-                .ok_or(ProgramError::ArithmeticOverflow)?
-                .checked_div(10000)
-                .ok_or(ProgramError::ArithmeticOverflow)?;
+            let total_votes = self.total_votes()?;
+            let quorum_threshold = basis_points_of(self.total_eligible_votes, self.quorum_percentage)?;
                 
             if total_votes < quorum_threshold {
                 self.status = ProposalStatus::Failed;
@@ -107,11 +139,7 @@ impl Proposal {
             }
             
             // Check approval threshold
-            let approval_threshold = total_votes
-                .checked_mul(self.approval_percentage as u64)
-                .ok_or(ProgramError::ArithmeticOverflow)?
-                .checked_div(10000)
-                .ok_or(ProgramError::ArithmeticOverflow)?;
+            let approval_threshold = basis_points_of(total_votes, self.approval_percentage)?;
                 
             if self.votes_for >= approval_threshold {
                 self.status = ProposalStatus::Passed;
@@ -123,10 +151,28 @@ impl Proposal {
         Ok(())
     }
     
-    pub fn add_vote(&mut self, vote_type: VoteType, voting_power: u64) -> Result<()> {
+    // Records a voter's choice. A voter changing their vote passes their
+    // previous choice, which is taken out of the tallies first.
+    pub fn record_vote(
+        &mut self,
+        previous_vote: Option<(VoteType, u64)>,
+        vote_type: VoteType,
+        voting_power: u64,
+        current_time: i64,
+    ) -> Result<()> {
         // Ensure voting is active
-        require!(self.is_active(), TitaErrors::VotingNotActive);
-        
+        require!(self.can_vote(current_time), TitaErrors::VotingNotActive);
+
+        if let Some((previous_type, previous_power)) = previous_vote {
+            require!(previous_type != vote_type, TitaErrors::AlreadyVoted);
+
+            self.remove_vote(previous_type, previous_power)?;
+        }
+
+        self.add_vote(vote_type, voting_power)
+    }
+
+    fn add_vote(&mut self, vote_type: VoteType, voting_power: u64) -> Result<()> {
         // Update vote counts
         match vote_type {
             VoteType::For => {
@@ -149,10 +195,33 @@ impl Proposal {
         Ok(())
     }
     
-    pub fn cancel(&mut self, canceler: &Pubkey) -> Result<()> {
-        // Only proposer can cancel, or if in Draft status
+    // Reverses a previously recorded vote, used when a voter changes their choice
+    fn remove_vote(&mut self, vote_type: VoteType, voting_power: u64) -> Result<()> {
+        match vote_type {
+            VoteType::For => {
+                self.votes_for = self.votes_for
+                    .checked_sub(voting_power)
+                    .ok_or(ProgramError::ArithmeticOverflow)?;
+            },
+            VoteType::Against => {
+                self.votes_against = self.votes_against
+                    .checked_sub(voting_power)
+                    .ok_or(ProgramError::ArithmeticOverflow)?;
+            },
+            VoteType::Abstain => {
+                self.votes_abstain = self.votes_abstain
+                    .checked_sub(voting_power)
+                    .ok_or(ProgramError::ArithmeticOverflow)?;
+            }
+        }
+
+        Ok(())
+    }
+    
+    pub fn cancel(&mut self, canceler: &Pubkey, flow_creator: &Pubkey) -> Result<()> {
+        // Only the proposer or the flow creator can cancel
         require!(
-            *canceler == self.proposer,
+            *canceler == self.proposer || *canceler == *flow_creator,
             TitaErrors::UnauthorizedCancellation
         );
         
@@ -161,6 +230,12 @@ impl Proposal {
             self.status != ProposalStatus::Executed,
             TitaErrors::ProposalAlreadyExecuted
         );
+
+        // Once anyone has voted the proposal has to run its course
+        require!(
+            self.votes_for == 0 && self.votes_against == 0 && self.votes_abstain == 0,
+            TitaErrors::VotesAlreadyCast
+        );
         
         self.status = ProposalStatus::Canceled;
         
@@ -169,20 +244,16 @@ impl Proposal {
     
     // Check if thresholds are met for auto-execution
     pub fn check_execution_threshold(&mut self) -> Result<bool> {
-        // Return early if not active
-        if self.status != ProposalStatus::Active {
+        // Return early if not active, nothing passes without support
+        if self.status != ProposalStatus::Active || !self.has_support() {
             return Ok(false);
         }
         
         // Calculate vote totals
-        let total_votes = self.votes_for + self.votes_against + self.votes_abstain;
+        let total_votes = self.total_votes()?;
         
         // Check quorum
-        let quorum_threshold = self.total_eligible_votes
-            .checked_mul(self.quorum_percentage as u64)
-            .ok_or(ProgramError::ArithmeticOverflow)?
-            .checked_div(10000)
-            .ok_or(ProgramError::ArithmeticOverflow)?;
+        let quorum_threshold = basis_points_of(self.total_eligible_votes, self.quorum_percentage)?;
             
         if total_votes < quorum_threshold {
             // Not enough votes yet
@@ -190,11 +261,7 @@ impl Proposal {
         }
         
         // Check approval threshold
-        let approval_threshold = total_votes
-            .checked_mul(self.approval_percentage as u64)
-            .ok_or(ProgramError::ArithmeticOverflow)?
-            .checked_div(10000)
-            .ok_or(ProgramError::ArithmeticOverflow)?;
+        let approval_threshold = basis_points_of(total_votes, self.approval_percentage)?;
             
         let should_execute = self.votes_for >= approval_threshold;
         
@@ -208,4 +275,102 @@ impl Proposal {
         
         Ok(should_execute)
     }
-}
\ No newline at end of file
+}
+
+// Share of `total` given in basis points, computed in u128 so large token
+// weighted tallies can't overflow
+fn basis_points_of(total: u64, basis_points: u16) -> Result<u64> {
+    let share = (total as u128)
+        .checked_mul(basis_points as u128)
+        .ok_or(TitaErrors::MathOverflow)?
+        / 10000;
+
+    Ok(share as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_proposal() -> Proposal {
+        Proposal {
+            flow: Pubkey::default(),
+            proposer: Pubkey::default(),
+            proposal_type: ProposalType::FlowCancellation,
+            status: ProposalStatus::Active,
+            created_at: 0,
+            voting_starts_at: 0,
+            voting_ends_at: 100,
+            votes_for: 0,
+            votes_against: 0,
+            votes_abstain: 0,
+            total_eligible_votes: 1_000,
+            snapshot_slot: 0,
+            snapshot_timestamp: 0,
+            executed_at: None,
+            quorum_percentage: 2_000,
+            approval_percentage: 5_000,
+            last_vote_check: 0,
+            bump: 255,
+        }
+    }
+
+    #[test]
+    fn record_vote_adds_to_the_chosen_tally() {
+        let mut proposal = test_proposal();
+
+        proposal.record_vote(None, VoteType::For, 300, 10).unwrap();
+        proposal.record_vote(None, VoteType::Against, 200, 10).unwrap();
+        proposal.record_vote(None, VoteType::Abstain, 100, 10).unwrap();
+        assert_eq!((proposal.votes_for, proposal.votes_against, proposal.votes_abstain), (300, 200, 100));
+        assert_eq!(proposal.total_votes().unwrap(), 600);
+    }
+
+    #[test]
+    fn record_vote_moves_weight_when_a_vote_changes() {
+        let mut proposal = test_proposal();
+        proposal.record_vote(None, VoteType::For, 300, 10).unwrap();
+        proposal.record_vote(None, VoteType::For, 200, 10).unwrap();
+
+        proposal.record_vote(Some((VoteType::For, 300)), VoteType::Against, 300, 20).unwrap();
+        assert_eq!((proposal.votes_for, proposal.votes_against), (200, 300));
+
+        proposal.record_vote(Some((VoteType::Against, 300)), VoteType::Abstain, 300, 30).unwrap();
+        assert_eq!((proposal.votes_for, proposal.votes_against, proposal.votes_abstain), (200, 0, 300));
+        assert_eq!(proposal.total_votes().unwrap(), 500);
+    }
+
+    #[test]
+    fn record_vote_rejects_the_same_choice_twice() {
+        let mut proposal = test_proposal();
+        proposal.record_vote(None, VoteType::For, 300, 10).unwrap();
+
+        assert_eq!(
+            proposal.record_vote(Some((VoteType::For, 300)), VoteType::For, 300, 20).unwrap_err(),
+            TitaErrors::AlreadyVoted.into()
+        );
+        assert_eq!(proposal.votes_for, 300);
+    }
+
+    #[test]
+    fn record_vote_only_while_voting_is_open() {
+        let mut proposal = test_proposal();
+        proposal.voting_starts_at = 10;
+
+        assert_eq!(
+            proposal.record_vote(None, VoteType::For, 300, 5).unwrap_err(),
+            TitaErrors::VotingNotActive.into()
+        );
+        assert_eq!(
+            proposal.record_vote(None, VoteType::For, 300, 101).unwrap_err(),
+            TitaErrors::VotingNotActive.into()
+        );
+
+        proposal.record_vote(None, VoteType::For, 300, 100).unwrap();
+        proposal.status = ProposalStatus::Passed;
+        assert_eq!(
+            proposal.record_vote(Some((VoteType::For, 300)), VoteType::Against, 300, 100).unwrap_err(),
+            TitaErrors::VotingNotActive.into()
+        );
+    }
+}
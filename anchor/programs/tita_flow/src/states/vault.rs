@@ -4,6 +4,8 @@ use anchor_lang::prelude::*;
 #[derive(InitSpace)]
 pub struct Vault {
     pub flow: Pubkey,            // Parent flow
+    pub milestone_id: u32,       // Milestone whose locked funds it holds
+    pub token_account: Pubkey,   // Token account holding them, owned by the flow PDA
     pub amount: u64,             // Amount allocated to this vault
 
     // For milestone vaults
@@ -11,4 +13,4 @@ pub struct Vault {
     pub milestone_completed: Option<bool>,
 
     pub bump: u8,
-}
\ No newline at end of file
+}
@@ -30,7 +30,7 @@ const provider = anchor.AnchorProvider.env();
   const flowId = "test-flow-1";
   const goal = new anchor.BN(1000000); // 1,000,000 tokens
   const now = Math.floor(Date.now() / 1000);
-  const startTime = new anchor.BN(now + 60);
+  const startTime = null; // open for contributions right away
   const endTime = new anchor.BN(now + 86400); // 1 day later
   const contributionAmount = new anchor.BN(100000); // 100,000 tokens
 
@@ -38,9 +38,11 @@ const provider = anchor.AnchorProvider.env();
   let flowPda: PublicKey;
   let milestoneFlowPda: PublicKey;
   let flowTokenAccount: PublicKey;
+  let milestoneFlowTokenAccount: PublicKey;
   let tokenMint: PublicKey;
   let contributorTokenAccount: PublicKey;
   let contributionPda: PublicKey;
+  let milestoneContributionPda: PublicKey;
 
 describe('tita_flow', () => {
   // Configure the client to use the local cluster.
@@ -110,7 +112,17 @@ describe('tita_flow', () => {
       startTime,
       endTime,
       { tokenWeighted: {} },
-      null // no milestones = direct flow
+      null, // default governance
+      null, // no milestones = direct flow
+      { raise: {} },
+      { flexible: {} },
+      { instant: {} },
+      { governance: {} },
+      false, // milestones can be completed in any order
+      false, // locked funds stay in flow_ta, no milestone vaults
+      null, // beneficiary defaults to the creator
+      null, // no payout splits
+      null // no contribution caps
     ).accountsPartial({
       creator: creator.publicKey,
       flow: flowPda,
@@ -131,7 +143,7 @@ describe('tita_flow', () => {
     assert.ok(flowAccount.tokenMint.equals(tokenMint));
     assert.ok(flowAccount.goal.eq(goal));
     assert.ok(flowAccount.raised.eq(new anchor.BN(0)));
-    assert.strictEqual(flowAccount.startDate?.toNumber(), startTime.toNumber());
+    assert.strictEqual(flowAccount.startDate, null);
     assert.strictEqual(flowAccount.endDate?.toNumber(), endTime.toNumber());
     assert.deepStrictEqual(flowAccount.flowStatus, { active: {} });
     assert.strictEqual(flowAccount.contributorCount, 0);
@@ -162,7 +174,7 @@ describe('tita_flow', () => {
     );
 
     // Find the flow token account PDA
-    [flowTokenAccount] = await PublicKey.findProgramAddressSync(
+    [milestoneFlowTokenAccount] = await PublicKey.findProgramAddressSync(
       [
         TITA_FLOW_TA_SEED,
         milestoneFlowPda.toBuffer(),
@@ -178,25 +190,33 @@ describe('tita_flow', () => {
       startTime,
       endTime,
       {tokenWeighted: {}},
+      null, // default governance
       [
         {
           id: 1,
           amount: new anchor.BN(500000), // 500,000 tokens
           deadline: new anchor.BN(now + 3600), // 1 hour later
-          completed: false,
         },
         {
           id: 2,
           amount: new anchor.BN(500000), // 500,000 tokens
           deadline: new anchor.BN(now + 7200), // 2 hours later
-          completed: false,
         },
-      ] // milestones
+      ], // milestones
+      { raise: {} },
+      { flexible: {} },
+      { instant: {} },
+      { governance: {} },
+      false, // milestones can be completed in any order
+      false, // locked funds stay in flow_ta, no milestone vaults
+      null, // beneficiary defaults to the creator
+      null, // no payout splits
+      null // no contribution caps
     )
       .accountsPartial({
         creator: creator.publicKey,
         flow: milestoneFlowPda,
-        flowTokenAccount: flowTokenAccount,
+        flowTokenAccount: milestoneFlowTokenAccount,
         tokenMint: tokenMint,
         tokenProgram: TOKEN_PROGRAM_ID,
         systemProgram: anchor.web3.SystemProgram.programId,
@@ -213,10 +233,19 @@ describe('tita_flow', () => {
     assert.ok(flowAccount.tokenMint.equals(tokenMint));
     assert.ok(flowAccount.goal.eq(goal));
     assert.ok(flowAccount.raised.eq(new anchor.BN(0)));
-    assert.strictEqual(flowAccount.startDate?.toNumber(), startTime.toNumber());
+    assert.strictEqual(flowAccount.startDate, null);
     assert.strictEqual(flowAccount.endDate?.toNumber(), endTime.toNumber());
     assert.deepStrictEqual(flowAccount.flowStatus, { active: {} });
     assert.strictEqual(flowAccount.contributorCount, 0);
+
+    [milestoneContributionPda] = await PublicKey.findProgramAddressSync(
+      [
+        TITA_CONTRIBUTION_SEED,
+        milestoneFlowPda.toBuffer(),
+        contributor.publicKey.toBuffer(),
+      ],
+      program.programId
+    );
   });
 
   it('should fail with empty flow ID', async () => {
@@ -234,7 +263,17 @@ describe('tita_flow', () => {
         startTime,
         endTime,
         { tokenWeighted: {} },
-        null // no milestones = direct flow
+        null, // default governance
+        null, // no milestones = direct flow
+        { raise: {} },
+        { flexible: {} },
+        { instant: {} },
+        { governance: {} },
+        false, // milestones can be completed in any order
+        false, // locked funds stay in flow_ta, no milestone vaults
+        null, // beneficiary defaults to the creator
+        null, // no payout splits
+        null // no contribution caps
       )
         .accountsPartial({
           creator: creator.publicKey,
@@ -269,7 +308,17 @@ describe('tita_flow', () => {
         startTime,
         endTime,
         { tokenWeighted: {} },
-        null // no milestones = direct flow
+        null, // default governance
+        null, // no milestones = direct flow
+        { raise: {} },
+        { flexible: {} },
+        { instant: {} },
+        { governance: {} },
+        false, // milestones can be completed in any order
+        false, // locked funds stay in flow_ta, no milestone vaults
+        null, // beneficiary defaults to the creator
+        null, // no payout splits
+        null // no contribution caps
       )
         .accountsPartial({
           creator: creator.publicKey,
@@ -346,29 +395,190 @@ describe('tita_flow', () => {
       assert.strictEqual(err.error.errorCode.number, 6012);
     }
   });
+
+  it('should open a pending flow once its start time passes', async () => {
+    const pendingFlowId = "test-pending-flow";
+    const pendingStartTime = new anchor.BN(Math.floor(Date.now() / 1000) + 5);
+
+    const [pendingFlowPda] = PublicKey.findProgramAddressSync(
+      [TITA_FLOW_SEED, Buffer.from(pendingFlowId), creator.publicKey.toBuffer()],
+      program.programId
+    );
+    const [pendingFlowTokenAccount] = PublicKey.findProgramAddressSync(
+      [TITA_FLOW_TA_SEED, pendingFlowPda.toBuffer(), tokenMint.toBuffer()],
+      program.programId
+    );
+    const [pendingContributionPda] = PublicKey.findProgramAddressSync(
+      [TITA_CONTRIBUTION_SEED, pendingFlowPda.toBuffer(), contributor.publicKey.toBuffer()],
+      program.programId
+    );
+
+    await program.methods.createFlow(
+      pendingFlowId,
+      goal,
+      pendingStartTime,
+      endTime,
+      { tokenWeighted: {} },
+      null, // default governance
+      null, // no milestones = direct flow
+      { raise: {} },
+      { flexible: {} },
+      { instant: {} },
+      { governance: {} },
+      false, // milestones can be completed in any order
+      false, // locked funds stay in flow_ta, no milestone vaults
+      null, // beneficiary defaults to the creator
+      null, // no payout splits
+      null // no contribution caps
+    ).accountsPartial({
+      creator: creator.publicKey,
+      flow: pendingFlowPda,
+      flowTokenAccount: pendingFlowTokenAccount,
+      tokenMint: tokenMint,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    })
+      .signers([creator])
+      .rpc();
+
+    let flowAccount = await program.account.flow.fetch(pendingFlowPda);
+    assert.strictEqual(flowAccount.startDate?.toNumber(), pendingStartTime.toNumber());
+    assert.deepStrictEqual(flowAccount.flowStatus, { pending: {} });
+
+    const contribute = () => program.methods.contribute(contributionAmount)
+      .accountsPartial({
+        contributor: contributor.publicKey,
+        flow: pendingFlowPda,
+        contribution: pendingContributionPda,
+        contributorTokenAccount: contributorTokenAccount,
+        flowTokenAccount: pendingFlowTokenAccount,
+        tokenMint: tokenMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([contributor])
+      .rpc();
+
+    // Contributions are turned away until the start time
+    try {
+      await contribute();
+      assert.fail("Should have thrown an error for a flow that hasn't started");
+    } catch (err: any) {
+      assert.strictEqual(err.error.errorCode.code, "FlowNotStarted");
+      assert.strictEqual(err.error.errorCode.number, 6018);
+    }
+
+    const waitMs = pendingStartTime.toNumber() * 1000 - Date.now();
+    await new Promise((resolve) => setTimeout(resolve, Math.max(waitMs, 0) + 1000));
+
+    // The first contribution after the start time opens the flow
+    await contribute();
+
+    flowAccount = await program.account.flow.fetch(pendingFlowPda);
+    assert.deepStrictEqual(flowAccount.flowStatus, { active: {} });
+    assert.ok(flowAccount.raised.eq(contributionAmount));
+  });
 });
 
 
 // Proposal and Voting Tests
 describe('proposal and voting', () => {
   let proposalPda: PublicKey;
-  const votingDuration = new anchor.BN(3600); // 1 hour
-  const quorumPercentage = 50; // 50%
-  const approvalPercentage = 60; // 60%
-
-  it('should create a proposal for milestone completion', async () => {
-    // Find proposal PDA
-    const flowAccount = await program.account.flow.fetch(milestoneFlowPda);
-    const proposalCount = flowAccount.proposalCount;
-    [proposalPda] = await PublicKey.findProgramAddressSync(
+  // Proposals can't be looser than the flow's default governance
+  const votingDuration = new anchor.BN(3 * 24 * 60 * 60); // 3 days
+  const quorumPercentage = 2000; // 20%
+  const approvalPercentage = 6000; // 60%
+
+  // Proposal PDAs are seeded with the flow's next proposal count
+  const nextProposalPda = async (flow: PublicKey) => {
+    const flowAccount = await program.account.flow.fetch(flow);
+    return PublicKey.findProgramAddressSync(
       [
         Buffer.from("tita-proposal"),
+        flow.toBuffer(),
+        Buffer.from([flowAccount.proposalCount + 1]),
+      ],
+      program.programId
+    )[0];
+  };
+
+  before(async () => {
+    // Only contributors can propose
+    await program.methods.contribute(contributionAmount)
+      .accountsPartial({
+        contributor: contributor.publicKey,
+        flow: milestoneFlowPda,
+        contribution: milestoneContributionPda,
+        contributorTokenAccount: contributorTokenAccount,
+        flowTokenAccount: milestoneFlowTokenAccount,
+        tokenMint: tokenMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([contributor])
+      .rpc();
+  });
+
+  it('should fail to create a proposal without contributing', async () => {
+    const [creatorContributionPda] = PublicKey.findProgramAddressSync(
+      [
+        TITA_CONTRIBUTION_SEED,
         milestoneFlowPda.toBuffer(),
-        Buffer.from([proposalCount]),
+        creator.publicKey.toBuffer(),
       ],
       program.programId
     );
 
+    try {
+      await program.methods.createProposal(
+        { flowCancellation: {} },
+        votingDuration,
+        quorumPercentage,
+        approvalPercentage
+      )
+        .accountsPartial({
+          proposer: creator.publicKey,
+          flow: milestoneFlowPda,
+          contribution: creatorContributionPda,
+          proposal: await nextProposalPda(milestoneFlowPda),
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([creator])
+        .rpc();
+
+      assert.fail("Should have thrown an error for a proposer without a contribution");
+    } catch (err: any) {
+      assert.strictEqual(err.error.errorCode.code, "AccountNotInitialized");
+    }
+  });
+
+  it('should fail to create a proposal looser than the flow governance', async () => {
+    try {
+      await program.methods.createProposal(
+        { flowCancellation: {} },
+        new anchor.BN(1), // 1 second
+        0, // no quorum
+        1 // any single vote passes
+      )
+        .accountsPartial({
+          proposer: contributor.publicKey,
+          flow: milestoneFlowPda,
+          contribution: milestoneContributionPda,
+          proposal: await nextProposalPda(milestoneFlowPda),
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([contributor])
+        .rpc();
+
+      assert.fail("Should have thrown an error for loose voting rules");
+    } catch (err: any) {
+      assert.strictEqual(err.error.errorCode.code, "GovernanceBelowFlowSettings");
+    }
+  });
+
+  it('should create a proposal for milestone completion', async () => {
+    proposalPda = await nextProposalPda(milestoneFlowPda);
+
     await program.methods.createProposal(
       {
         milestoneCompletion: {
@@ -380,18 +590,19 @@ describe('proposal and voting', () => {
       approvalPercentage
     )
       .accountsPartial({
-        proposer: creator.publicKey,
+        proposer: contributor.publicKey,
         flow: milestoneFlowPda,
+        contribution: milestoneContributionPda,
         proposal: proposalPda,
         systemProgram: anchor.web3.SystemProgram.programId,
       })
-      .signers([creator])
+      .signers([contributor])
       .rpc();
 
     // Verify proposal account
     const proposalAccount = await program.account.proposal.fetch(proposalPda);
     assert.ok(proposalAccount.flow.equals(milestoneFlowPda));
-    assert.ok(proposalAccount.proposer.equals(creator.publicKey));
+    assert.ok(proposalAccount.proposer.equals(contributor.publicKey));
     assert.deepStrictEqual(proposalAccount.proposalType, {
       milestoneCompletion: { milestoneId: 1 },
     });
@@ -539,6 +750,116 @@ describe('proposal and voting', () => {
   // });
 });
 
+// Beneficiary Rotation Tests
+describe('beneficiary rotation', () => {
+  const rotationFlowId = "test-rotation-flow-1";
+  let rotationFlowPda: PublicKey;
+  let rotationFlowTokenAccount: PublicKey;
+  let rotationContributionPda: PublicKey;
+
+  before(async () => {
+    [rotationFlowPda] = PublicKey.findProgramAddressSync(
+      [TITA_FLOW_SEED, Buffer.from(rotationFlowId), creator.publicKey.toBuffer()],
+      program.programId
+    );
+    [rotationFlowTokenAccount] = PublicKey.findProgramAddressSync(
+      [TITA_FLOW_TA_SEED, rotationFlowPda.toBuffer(), tokenMint.toBuffer()],
+      program.programId
+    );
+    [rotationContributionPda] = PublicKey.findProgramAddressSync(
+      [TITA_CONTRIBUTION_SEED, rotationFlowPda.toBuffer(), contributor.publicKey.toBuffer()],
+      program.programId
+    );
+
+    // Short votes without quorum, so the proposal can be finalized straight away
+    await program.methods.createFlow(
+      rotationFlowId,
+      goal,
+      null, // open right away
+      endTime,
+      { tokenWeighted: {} },
+      { votingPeriod: new anchor.BN(1), quorumPercentage: 0, approvalPercentage: 5000 },
+      null, // no milestones = direct flow
+      { raise: {} },
+      { flexible: {} },
+      { instant: {} },
+      { governance: {} },
+      false, // milestones can be completed in any order
+      false, // locked funds stay in flow_ta, no milestone vaults
+      null, // beneficiary defaults to the creator
+      null, // no payout splits
+      null // no contribution caps
+    )
+      .accountsPartial({
+        creator: creator.publicKey,
+        flow: rotationFlowPda,
+        flowTokenAccount: rotationFlowTokenAccount,
+        tokenMint: tokenMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([creator])
+      .rpc();
+
+    await program.methods.contribute(contributionAmount)
+      .accountsPartial({
+        contributor: contributor.publicKey,
+        flow: rotationFlowPda,
+        contribution: rotationContributionPda,
+        contributorTokenAccount: contributorTokenAccount,
+        flowTokenAccount: rotationFlowTokenAccount,
+        tokenMint: tokenMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([contributor])
+      .rpc();
+  });
+
+  it('should fail a ChangeBeneficiary proposal nobody voted for', async () => {
+    const attacker = anchor.web3.Keypair.generate();
+    const [proposalPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("tita-proposal"), rotationFlowPda.toBuffer(), Buffer.from([1])],
+      program.programId
+    );
+
+    await program.methods.createProposal(
+      { changeBeneficiary: { newBeneficiary: attacker.publicKey } },
+      new anchor.BN(1), // the flow's voting period
+      0, // the flow's quorum
+      5000 // the flow's approval
+    )
+      .accountsPartial({
+        proposer: contributor.publicKey,
+        flow: rotationFlowPda,
+        contribution: rotationContributionPda,
+        proposal: proposalPda,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([contributor])
+      .rpc();
+
+    // Let the voting period run out without any votes
+    await new Promise((resolve) => setTimeout(resolve, 3000));
+
+    await program.methods.finalizeProposal()
+      .accountsPartial({
+        cranker: attacker.publicKey,
+        proposal: proposalPda,
+        flow: rotationFlowPda,
+      })
+      .signers([attacker])
+      .rpc();
+
+    const proposalAccount = await program.account.proposal.fetch(proposalPda);
+    assert.deepStrictEqual(proposalAccount.status, { failed: {} });
+
+    const flowAccount = await program.account.flow.fetch(rotationFlowPda);
+    assert.ok(flowAccount.beneficiary.equals(creator.publicKey));
+    assert.strictEqual(flowAccount.activeProposal, null);
+  });
+});
+
 // Refund Tests
 // describe('refund functionality', () => {
 //   it('should refund contribution when flow is canceled', async () => {